@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+
+use crate::field::*;
+
+// 1行分のビット列。x列目が (1 << x) に対応する
+pub type Row = u16;
+
+const _: () = assert!(FIELD_W <= Row::BITS as usize);
+
+// 角の位置をブロックのidごとに持つ
+pub type Corners = HashMap<Cell, (usize, usize)>;
+
+// Fieldを行ごとのビットマスクと1バイトのidで表したもの
+// 探索で大量に複製・評価するための表現で、APIはFieldと同じ
+// ヒープを使わず、Fieldの4分の1ほどの大きさなので、複製はただの小さなメモリのコピーになる
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BitField {
+    pub occupancy: [Row; FIELD_H],
+    ids: [[u8; FIELD_W]; FIELD_H], // マスごとのブロックのid（ASCII）。空白はEMPTY
+    same_up: [Row; FIELD_H],       // 上のセルと同じブロックであるセル
+    same_left: [Row; FIELD_H],     // 左のセルと同じブロックであるセル
+}
+
+fn span(left: usize, right: usize) -> Row {
+    (((1u32 << (right + 1)) - 1) & !((1u32 << left) - 1)) as Row
+}
+
+fn has_bit(row: Row, x: usize) -> bool {
+    x < FIELD_W && row & (1 << x) != 0
+}
+
+// ブロックのidはASCII文字に限る
+fn id_byte(id: Cell) -> u8 {
+    assert!(id.is_ascii(), "block id must be ASCII: {:?}", id);
+    id as u8
+}
+
+impl Default for BitField {
+    fn default() -> Self {
+        BitField::new()
+    }
+}
+
+impl BitField {
+    pub fn new() -> BitField {
+        BitField {
+            occupancy: [0; FIELD_H],
+            ids: [[EMPTY as u8; FIELD_W]; FIELD_H],
+            same_up: [0; FIELD_H],
+            same_left: [0; FIELD_H],
+        }
+    }
+
+    pub fn from_field(field: &Field) -> BitField {
+        let mut bit_field = BitField::new();
+        for y in 0..FIELD_H {
+            for x in 0..FIELD_W {
+                let ch = field.cells[y][x];
+                if ch != EMPTY {
+                    bit_field.ids[y][x] = id_byte(ch);
+                    bit_field.occupancy[y] |= 1 << x;
+                }
+            }
+        }
+        for y in 0..FIELD_H {
+            bit_field.update_row_masks(y);
+        }
+        bit_field
+    }
+
+    pub fn to_field(&self) -> Field {
+        let mut field = Field::new();
+        for y in 0..FIELD_H {
+            for x in 0..FIELD_W {
+                field.cells[y][x] = self.get(x, y);
+            }
+        }
+        field
+    }
+
+    // y行目の埋まっているマスのx座標
+    fn occupied(&self, y: usize) -> impl Iterator<Item = usize> {
+        let row = self.occupancy[y];
+        (0..FIELD_W).filter(move |x| has_bit(row, *x))
+    }
+
+    fn update_row_masks(&mut self, y: usize) {
+        let mut same_up = 0;
+        let mut same_left = 0;
+        for x in self.occupied(y) {
+            let id = self.ids[y][x];
+            if y >= 1 && has_bit(self.occupancy[y - 1], x) && self.ids[y - 1][x] == id {
+                same_up |= 1 << x;
+            }
+            if x >= 1 && has_bit(self.occupancy[y], x - 1) && self.ids[y][x - 1] == id {
+                same_left |= 1 << x;
+            }
+        }
+        self.same_up[y] = same_up;
+        self.same_left[y] = same_left;
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Cell {
+        if x >= FIELD_W || y >= FIELD_H || !has_bit(self.occupancy[y], x) {
+            return EMPTY;
+        }
+        self.ids[y][x] as Cell
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, ch: Cell) {
+        let bit: Row = 1 << x;
+        if ch == EMPTY {
+            self.occupancy[y] &= !bit;
+            self.ids[y][x] = EMPTY as u8;
+        } else {
+            self.occupancy[y] |= bit;
+            self.ids[y][x] = id_byte(ch);
+        }
+
+        self.update_row_masks(y);
+        if y + 1 < FIELD_H {
+            self.update_row_masks(y + 1);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.occupancy.iter().all(|row| *row == 0)
+    }
+
+    pub fn is_rectangle(&self, left: usize, top: usize, right: usize, bottom: usize) -> bool {
+        // 幅または高さが1の矩形は除外
+        if !(left < right && top < bottom) {
+            return false;
+        }
+
+        // 上下の辺がつながっていて、上下に飛び出ていないか判定
+        let mask = span(left, right);
+        if self.occupancy[top] & mask != mask || self.same_up[top] & mask != 0 {
+            return false;
+        }
+        if self.occupancy[bottom] & mask != mask {
+            return false;
+        }
+        if bottom + 1 < FIELD_H && self.same_up[bottom + 1] & mask != 0 {
+            return false;
+        }
+
+        // 左右の辺がつながっていて、左右に飛び出ていないか判定
+        for y in top..=bottom {
+            if !has_bit(self.occupancy[y], left) || has_bit(self.same_left[y], left) {
+                return false;
+            }
+            if !has_bit(self.occupancy[y], right) || has_bit(self.same_left[y], right + 1) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub fn find_corners(&self) -> (Corners, Corners) {
+        let mut top_lefts = Corners::new();
+        let mut bottom_rights = Corners::new();
+        for y in 0..FIELD_H {
+            for x in self.occupied(y) {
+                let id = self.ids[y][x] as Cell;
+                top_lefts.entry(id).or_insert((x, y));
+                let br = bottom_rights.entry(id).or_insert((x, y));
+                br.0 = br.0.max(x);
+                br.1 = y;
+            }
+        }
+        (top_lefts, bottom_rights)
+    }
+
+    pub fn find_all_rectangles(&self) -> Vec<Rectangle> {
+        let (top_lefts, bottom_rights) = self.find_corners();
+
+        let mut answers = Vec::new();
+        for tl in top_lefts.values() {
+            for br in bottom_rights.values() {
+                let (left, top) = *tl;
+                let (right, bottom) = *br;
+                if self.is_rectangle(left, top, right, bottom) {
+                    answers.push(Rectangle {
+                        left,
+                        top,
+                        right,
+                        bottom,
+                    });
+                }
+            }
+        }
+        answers
+    }
+
+    pub fn find_rectangle_to_be_erased(&self, x: usize, y: usize) -> Option<Rectangle> {
+        let mut rectangles = self.find_all_rectangles();
+        rectangles.sort_by_key(|r| r.erase_priority());
+        rectangles
+            .into_iter()
+            .find(|r| r.left <= x && x <= r.right && r.top <= y && y <= r.bottom)
+    }
+}
+
+impl From<&Field> for BitField {
+    fn from(field: &Field) -> Self {
+        BitField::from_field(field)
+    }
+}
+
+impl From<&BitField> for Field {
+    fn from(bit_field: &BitField) -> Self {
+        bit_field.to_field()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ERASING;
+    use proptest::prelude::*;
+
+    fn random_field() -> impl Strategy<Value = Field> {
+        let cell = prop::sample::select(vec![EMPTY, EMPTY, 'a', 'b', 'c', 'd', ERASING]);
+        prop::collection::vec(cell, FIELD_W * FIELD_H).prop_map(|cells| {
+            let mut field = Field::new();
+            for (i, ch) in cells.into_iter().enumerate() {
+                field.cells[i / FIELD_W][i % FIELD_W] = ch;
+            }
+            field
+        })
+    }
+
+    fn sorted(mut rectangles: Vec<Rectangle>) -> Vec<Rectangle> {
+        rectangles.sort_by_key(|r| (r.left, r.top, r.right, r.bottom));
+        rectangles
+    }
+
+    #[test]
+    fn test_smaller_than_field() {
+        assert!(std::mem::size_of::<BitField>() * 2 < std::mem::size_of::<Field>());
+    }
+
+    #[test]
+    fn test_same_area_rectangles_are_chosen_in_fixed_order() {
+        #[rustfmt::skip]
+        let field = Field::from_text(concat!(
+            "aab\n",
+            "aab\n",
+            "aab\n",
+            "aab\n",
+            "cc\n",
+            "cc\n",
+        ));
+        let expected = Some(Rectangle {
+            left: 0,
+            top: 0,
+            right: 2,
+            bottom: 3,
+        });
+        for _ in 0..20 {
+            assert_eq!(field.find_rectangle_to_be_erased(0, 0), expected);
+            assert_eq!(
+                BitField::from_field(&field).find_rectangle_to_be_erased(0, 0),
+                expected
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn prop_same_results_as_field(field in random_field(), x in 0..FIELD_W, y in 0..FIELD_H) {
+            let bit_field = BitField::from_field(&field);
+            prop_assert_eq!(bit_field.to_field(), field.clone());
+            prop_assert_eq!(bit_field.find_corners(), field.find_corners());
+            prop_assert_eq!(
+                sorted(bit_field.find_all_rectangles()),
+                sorted(field.find_all_rectangles())
+            );
+            prop_assert_eq!(
+                bit_field.find_rectangle_to_be_erased(x, y),
+                field.find_rectangle_to_be_erased(x, y)
+            );
+        }
+
+        #[test]
+        fn prop_set_matches_rebuild(
+            field in random_field(),
+            x in 0..FIELD_W,
+            y in 0..FIELD_H,
+            ch in prop::sample::select(vec![EMPTY, 'a', 'e']),
+        ) {
+            let mut bit_field = BitField::from_field(&field);
+            bit_field.set(x, y, ch);
+            let mut expected = field.clone();
+            expected.cells[y][x] = ch;
+            prop_assert_eq!(bit_field, BitField::from_field(&expected));
+        }
+    }
+}
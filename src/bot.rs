@@ -0,0 +1,211 @@
+use serde_json::json;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::field::*;
+use crate::model::*;
+
+// ボットとの接続の読み込み側と書き込み側
+type BotStream = (Box<dyn Read + Send>, Box<dyn Write + Send>);
+
+// 外部のボットと1行1メッセージのJSONでやりとりするサーバー
+//
+// サーバー -> ボット: 毎フレームの状態 {"type":"state","frame":0,"field":["...",...],...}
+// ボット -> サーバー: コマンド {"command":"Left"}
+pub struct BotServer {
+    writer: Box<dyn Write + Send>,
+    commands: Receiver<Command>,
+    lockstep: bool, // trueならボットのコマンドが届くまで待つ
+}
+
+impl BotServer {
+    // addressが"unix:"で始まる場合はUnixドメインソケット、それ以外はTCPで待ち受ける
+    pub fn listen(address: &str, lockstep: bool) -> Result<BotServer, String> {
+        println!("Waiting for bot on {}", address);
+        let (reader, writer): BotStream = if let Some(path) = address.strip_prefix("unix:") {
+            accept_unix(path)?
+        } else {
+            let listener = TcpListener::bind(address).map_err(|e| e.to_string())?;
+            let (stream, peer) = listener.accept().map_err(|e| e.to_string())?;
+            println!("Bot connected from {}", peer);
+            let reader = stream.try_clone().map_err(|e| e.to_string())?;
+            (Box::new(reader), Box::new(stream))
+        };
+
+        Ok(BotServer::new(reader, writer, lockstep))
+    }
+
+    pub fn new(
+        reader: Box<dyn Read + Send>,
+        writer: Box<dyn Write + Send>,
+        lockstep: bool,
+    ) -> BotServer {
+        let (sender, commands) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let command = parse_command(&line).unwrap_or_else(|| {
+                    eprintln!("invalid command from bot: {}", line);
+                    Command::None
+                });
+                if sender.send(command).is_err() {
+                    break;
+                }
+            }
+        });
+
+        BotServer {
+            writer,
+            commands,
+            lockstep,
+        }
+    }
+
+    pub fn send_state(&mut self, game: &Game) -> Result<(), String> {
+        writeln!(self.writer, "{}", state_json(game)).map_err(|e| e.to_string())?;
+        self.writer.flush().map_err(|e| e.to_string())
+    }
+
+    // このフレームで使うコマンドを受け取る
+    // lockstepでなければ待たずに、前のフレーム以降に届いた最後のコマンドを使う
+    pub fn receive_command(&mut self) -> Result<Command, String> {
+        if self.lockstep {
+            return self
+                .commands
+                .recv()
+                .map_err(|_| "bot disconnected".to_string());
+        }
+
+        let mut command = Command::None;
+        loop {
+            match self.commands.try_recv() {
+                Ok(c) => command = c,
+                Err(TryRecvError::Empty) => return Ok(command),
+                Err(TryRecvError::Disconnected) => return Err("bot disconnected".to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn accept_unix(path: &str) -> Result<BotStream, String> {
+    use std::os::unix::net::UnixListener;
+
+    std::fs::remove_file(path).ok();
+    let listener = UnixListener::bind(path).map_err(|e| e.to_string())?;
+    let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+    println!("Bot connected");
+    let reader = stream.try_clone().map_err(|e| e.to_string())?;
+    Ok((Box::new(reader), Box::new(stream)))
+}
+
+#[cfg(not(unix))]
+fn accept_unix(_path: &str) -> Result<BotStream, String> {
+    Err("unix sockets are not supported on this platform".to_string())
+}
+
+pub fn parse_command(line: &str) -> Option<Command> {
+    let value: serde_json::Value = serde_json::from_str(line).ok()?;
+    let command = value.get("command")?.as_str()?;
+    match command {
+        "None" | "Left" | "Right" | "Up" | "Shoot" => Some(Command::from_str(command)),
+        _ => None,
+    }
+}
+
+pub fn field_rows(field: &Field) -> Vec<String> {
+    field
+        .cells
+        .iter()
+        .map(|row| row.iter().collect::<String>())
+        .collect()
+}
+
+pub fn state_json(game: &Game) -> serde_json::Value {
+    let bullets: Vec<serde_json::Value> = game
+        .bullets
+        .iter()
+        .map(|b| json!({"x": b.pos.x, "y": b.pos.y, "offset_y": b.offset_y, "owner": b.owner}))
+        .collect();
+    let players: Vec<serde_json::Value> = game
+        .players
+        .iter()
+        .map(|p| json!({"x": p.x, "score": p.score}))
+        .collect();
+    json!({
+        "type": "state",
+        "frame": game.frame,
+        "field": field_rows(&game.field),
+        "player_x": game.players[0].x,
+        "players": players,
+        "bullets": bullets,
+        "scroll_wait": game.scroll_wait,
+        "next_row": game.next_row,
+        "score": game.score,
+        "progress": game.get_progress(),
+        "is_over": game.is_over,
+        "is_clear": game.is_clear,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_lockstep_session() {
+        let input = "{\"command\":\"Left\"}\n\n{\"command\":\"Shoot\"}\nbroken\n";
+        let output = SharedBuffer::default();
+        let mut server = BotServer::new(
+            Box::new(std::io::Cursor::new(input)),
+            Box::new(output.clone()),
+            true,
+        );
+
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.load_stage("resources/data/stage1.dat");
+        let mut commands = Vec::new();
+        loop {
+            server.send_state(&game).unwrap();
+            let Ok(command) = server.receive_command() else {
+                break;
+            };
+            game.update(command);
+            commands.push(command);
+        }
+
+        assert_eq!(commands, vec![Command::Left, Command::Shoot, Command::None]);
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let states: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(states.len(), 4);
+        assert_eq!(states[0]["frame"], -1);
+        assert_eq!(states[0]["field"].as_array().unwrap().len(), FIELD_H);
+        assert_eq!(states[3]["frame"], 2);
+        assert_eq!(states[3]["bullets"][0]["x"], game.players[0].x);
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// フレームをまたいで使い回す値のキャッシュ。文字のテクスチャなど
+// 1フレームの間に一度も使われなかったものはend_frameで捨てる
+pub struct FrameCache<K, V> {
+    entries: HashMap<K, (V, bool)>, // (値, このフレームで使ったか)
+}
+
+impl<K: Eq + Hash, V> FrameCache<K, V> {
+    pub fn new() -> FrameCache<K, V> {
+        FrameCache {
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // キャッシュになければキーからcreateで作る
+    pub fn get_or_try_insert_with<E>(
+        &mut self,
+        key: K,
+        create: impl FnOnce(&K) -> Result<V, E>,
+    ) -> Result<&V, E> {
+        let entry = match self.entries.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let value = create(entry.key())?;
+                entry.insert((value, false))
+            }
+        };
+        entry.1 = true;
+        Ok(&entry.0)
+    }
+
+    // 1フレーム描き終えたら呼ぶ
+    pub fn end_frame(&mut self) {
+        self.entries.retain(|_, (_, used)| *used);
+        for (_, used) in self.entries.values_mut() {
+            *used = false;
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Default for FrameCache<K, V> {
+    fn default() -> Self {
+        FrameCache::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse_and_evict() {
+        let mut cache: FrameCache<&str, usize> = FrameCache::new();
+        let mut created = 0;
+        let mut get = |cache: &mut FrameCache<&str, usize>, key| {
+            *cache
+                .get_or_try_insert_with(key, |key| {
+                    created += 1;
+                    Ok::<usize, ()>(key.len())
+                })
+                .unwrap()
+        };
+
+        assert_eq!(get(&mut cache, "score"), 5);
+        assert_eq!(get(&mut cache, "pct"), 3);
+        cache.end_frame();
+        assert_eq!(cache.len(), 2);
+
+        // 次のフレームで使ったものだけ残る
+        assert_eq!(get(&mut cache, "score"), 5);
+        cache.end_frame();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(get(&mut cache, "score"), 5);
+        assert_eq!(get(&mut cache, "pct"), 3);
+        assert_eq!(created, 3);
+
+        cache.end_frame();
+        cache.end_frame();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_failed_create_is_not_cached() {
+        let mut cache: FrameCache<i32, i32> = FrameCache::new();
+        assert_eq!(cache.get_or_try_insert_with(1, |_| Err("no")), Err("no"));
+        assert!(cache.is_empty());
+        assert_eq!(
+            cache.get_or_try_insert_with(1, |key| Ok::<_, ()>(key * 7)),
+            Ok(&7)
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::field::*;
+use crate::model::*;
+use crate::palette::*;
+
+// 消えた1マスから飛び散る粒の数
+const PARTICLES_PER_CELL: usize = 4;
+const PARTICLE_LIFE: i32 = 24;
+const GRAVITY: f32 = 0.4;
+
+// 画面の揺れ。矩形の面積1マスあたりの揺れ幅と上限（ピクセル）
+const SHAKE_PER_CELL: f32 = 0.5;
+const SHAKE_MAX: f32 = 12.0;
+const SHAKE_DECAY: f32 = 0.8;
+
+// 何種類ものブロックを含む大きな矩形を消したときは画面を光らせる
+const FLASH_BLOCK_COUNT: i32 = 3;
+const FLASH_AREA: usize = 12;
+pub const FLASH_FRAMES: i32 = 8;
+
+#[derive(Debug, Clone)]
+pub struct Particle {
+    pub x: f32, // フィールド左上からのピクセル位置
+    pub y: f32,
+    pub vx: f32,
+    pub vy: f32,
+    pub color: (u8, u8, u8),
+    pub life: i32, // 残りフレーム数
+}
+
+// ゲームのイベントから作る見た目だけの演出。ゲームの状態には影響しない
+// 毎フレーム、Gameから取り出したイベントを渡してupdateを呼ぶ
+pub struct Effects {
+    pub particles: Vec<Particle>,
+    pub shake: f32,                      // 揺れ幅（ピクセル）
+    pub flash: i32,                      // 光らせる残りフレーム数
+    erasing: Vec<(Point, (u8, u8, u8))>, // 消去中のマスと、消える前の色
+    rng: StdRng,
+}
+
+impl Effects {
+    pub fn new() -> Effects {
+        Effects {
+            particles: Vec::new(),
+            shake: 0.0,
+            flash: 0,
+            erasing: Vec::new(),
+            rng: StdRng::seed_from_u64(0),
+        }
+    }
+
+    // scroll_offsetはGame::scroll_offset。粒はフィールドと同じだけ下げた位置から出す
+    pub fn update(
+        &mut self,
+        events: &[GameEvent],
+        field: &Field,
+        scroll_offset: f32,
+        colors: &BlockColors,
+    ) {
+        for particle in &mut self.particles {
+            particle.x += particle.vx;
+            particle.y += particle.vy;
+            particle.vy += GRAVITY;
+            particle.life -= 1;
+        }
+        self.particles.retain(|particle| particle.life > 0);
+        self.shake *= SHAKE_DECAY;
+        if self.shake < 0.5 {
+            self.shake = 0.0;
+        }
+        self.flash = (self.flash - 1).max(0);
+
+        for event in events {
+            if let GameEvent::EraseStarted {
+                rectangle,
+                block_count,
+                blocks,
+                ..
+            } = event
+            {
+                self.erasing.extend(
+                    blocks
+                        .iter()
+                        .map(|&(pos, cell)| (pos, colors.color_of(cell))),
+                );
+                let area = rectangle.area();
+                self.shake = self
+                    .shake
+                    .max((area as f32 * SHAKE_PER_CELL).min(SHAKE_MAX));
+                if *block_count >= FLASH_BLOCK_COUNT && area >= FLASH_AREA {
+                    self.flash = FLASH_FRAMES;
+                }
+            }
+        }
+
+        // 消えきったマスから粒を出す
+        let (erased, erasing) = self
+            .erasing
+            .iter()
+            .partition(|(pos, _)| field.cells[pos.y][pos.x] != ERASING);
+        self.erasing = erasing;
+        for (pos, color) in erased {
+            self.spawn_particles(pos, color, scroll_offset);
+        }
+    }
+
+    fn spawn_particles(&mut self, pos: Point, color: (u8, u8, u8), scroll_offset: f32) {
+        let x = (pos.x as i32 * CELL_SIZE + CELL_SIZE / 2) as f32;
+        let y =
+            (pos.y as i32 * CELL_SIZE + CELL_SIZE / 2) as f32 + scroll_offset * CELL_SIZE as f32;
+        for _ in 0..PARTICLES_PER_CELL {
+            self.particles.push(Particle {
+                x,
+                y,
+                vx: self.rng.gen_range(-3.0..3.0),
+                vy: self.rng.gen_range(-5.0..-1.0),
+                color,
+                life: self.rng.gen_range(PARTICLE_LIFE / 2..=PARTICLE_LIFE),
+            });
+        }
+    }
+
+    // このフレームで画面をずらす量
+    pub fn shake_offset(&mut self) -> (i32, i32) {
+        if self.shake == 0.0 {
+            return (0, 0);
+        }
+        let shake = self.shake;
+        (
+            self.rng.gen_range(-shake..=shake) as i32,
+            self.rng.gen_range(-shake..=shake) as i32,
+        )
+    }
+}
+
+impl Default for Effects {
+    fn default() -> Self {
+        Effects::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::*;
+
+    #[test]
+    fn test_particles_come_from_erased_cells() {
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.scroll_wait = 1000;
+        game.field.cells[0][8] = 'a';
+        game.field.cells[0][9] = 'a';
+        game.field.cells[1][9] = 'a';
+        let mut colors = BlockColors::new(Palette::load("default").unwrap());
+        colors.assign(&game.field);
+        let a = colors.color(8, 0).unwrap();
+
+        let mut effects = Effects::new();
+        let mut started = false;
+        let mut spawned = 0;
+        let mut shaken = false;
+        for frame in 0..30 {
+            game.update(if frame == 0 {
+                Command::Shoot
+            } else {
+                Command::None
+            });
+            let events = std::mem::take(&mut game.events);
+            started |= !events.is_empty();
+            let before = effects.particles.len();
+            effects.update(&events, &game.field, game.scroll_offset(), &colors);
+            shaken |= effects.shake > 0.0;
+            if effects.particles.len() > before {
+                spawned += 1;
+            }
+            if started && game.erasing_effects.is_empty() {
+                break;
+            }
+        }
+
+        // 弾で埋まったマスを含めて1マスずつ消えるので、粒も4つのフレームに分かれて出る
+        assert!(started);
+        assert_eq!(spawned, 4);
+        assert!(effects.particles.iter().all(|p| p.color == a));
+        assert!(shaken);
+        assert_eq!(effects.flash, 0);
+
+        for _ in 0..PARTICLE_LIFE + 1 {
+            effects.update(&[], &game.field, game.scroll_offset(), &colors);
+        }
+        assert!(effects.particles.is_empty());
+        assert_eq!(effects.shake, 0.0);
+        assert_eq!(effects.shake_offset(), (0, 0));
+    }
+
+    #[test]
+    fn test_particles_follow_scroll_offset() {
+        let mut effects = Effects::new();
+        effects.spawn_particles(Point::new(2, 3), (255, 255, 255), 0.5);
+        let y = (3 * CELL_SIZE + CELL_SIZE / 2 + CELL_SIZE / 2) as f32;
+        assert!(effects.particles.iter().all(|p| p.y == y));
+    }
+
+    #[test]
+    fn test_flash_on_large_erase() {
+        let colors = BlockColors::new(Palette::load("default").unwrap());
+        let mut effects = Effects::new();
+        let event = |right, block_count| GameEvent::EraseStarted {
+            rectangle: Rectangle {
+                left: 0,
+                top: 0,
+                right,
+                bottom: 2,
+            },
+            block_count,
+            player: 0,
+            blocks: Vec::new(),
+        };
+
+        effects.update(&[event(1, 4)], &Field::new(), 0.0, &colors);
+        assert_eq!(effects.flash, 0);
+        let small_shake = effects.shake;
+
+        effects.update(&[event(3, 2)], &Field::new(), 0.0, &colors);
+        assert_eq!(effects.flash, 0);
+        effects.update(&[event(3, 3)], &Field::new(), 0.0, &colors);
+        assert_eq!(effects.flash, FLASH_FRAMES);
+        assert!(effects.shake > small_shake);
+    }
+}
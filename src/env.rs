@@ -0,0 +1,93 @@
+use crate::field::*;
+use crate::model::*;
+use crate::recorder::*;
+
+// エージェントに渡す観測
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Observation {
+    pub field: Field,
+    pub player_x: usize,
+    pub bullets: Vec<Point>,
+    pub scroll_wait: i32,
+    pub next_row: usize,
+}
+
+impl Observation {
+    pub fn new(game: &Game) -> Observation {
+        Observation {
+            field: game.field.clone(),
+            player_x: game.players[0].x,
+            bullets: game.bullets.iter().map(|b| b.pos).collect(),
+            scroll_wait: game.scroll_wait,
+            next_row: game.next_row,
+        }
+    }
+}
+
+// 強化学習用の環境。描画せずにGameを1フレームずつ進める
+#[derive(Debug, Clone)]
+pub struct Env {
+    pub game: Game,
+}
+
+impl Env {
+    pub fn new(seed: u64, stage: &str) -> Env {
+        let mut game = Game::with_seed(seed, Box::new(NullRecorder));
+        game.load_stage(stage);
+        Env { game }
+    }
+
+    pub fn reset(&mut self, seed: u64, stage: &str) -> Observation {
+        *self = Env::new(seed, stage);
+        Observation::new(&self.game)
+    }
+
+    // 報酬はスコアの増分
+    pub fn step(&mut self, action: Command) -> (Observation, i32, bool) {
+        let score = self.game.score;
+        self.game.update(action);
+        self.game.requested_sounds.clear();
+        self.game.events.clear();
+
+        let reward = self.game.score - score;
+        let done = self.game.is_over || self.game.is_clear;
+        (Observation::new(&self.game), reward, done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAGE: &str = "resources/data/stage1.dat";
+
+    #[test]
+    fn test_same_seed_same_episode() {
+        let actions = [
+            Command::Shoot,
+            Command::Left,
+            Command::Shoot,
+            Command::Up,
+            Command::Right,
+        ];
+        let mut env1 = Env::new(0, STAGE);
+        let mut env2 = Env::new(0, STAGE);
+        let mut total_reward = 0;
+        let mut done = false;
+        for i in 0..3000 {
+            let action = actions[i % actions.len()];
+            let result = env1.step(action);
+            assert_eq!(result, env2.step(action));
+            total_reward += result.1;
+            done = result.2;
+            if done {
+                break;
+            }
+        }
+
+        assert!(done);
+        assert_eq!(total_reward, env1.game.score);
+        assert_eq!(env1.reset(0, STAGE), env2.reset(0, STAGE));
+        assert_eq!(env1.game.score, 0);
+    }
+}
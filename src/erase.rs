@@ -0,0 +1,221 @@
+use crate::field::*;
+use crate::model::*;
+
+// 消去中の矩形（ERASINGのマス）をどんな順番で空白にしていくか
+// どの消し方もERASE_WAITごとに1回stepを呼び、1回で少なくとも1マス消すので必ず消えきる
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub enum EraseStyle {
+    #[default]
+    Spiral, // 左下から渦巻き状に1マスずつ
+    Wipe,    // 下の行から1行ずつ
+    Burst,   // 中心から外側へ1周ずつ
+    Instant, // 一度に全部
+}
+
+pub const ERASE_STYLES: [EraseStyle; 4] = [
+    EraseStyle::Spiral,
+    EraseStyle::Wipe,
+    EraseStyle::Burst,
+    EraseStyle::Instant,
+];
+
+impl EraseStyle {
+    pub fn from_name(name: &str) -> Result<EraseStyle, String> {
+        ERASE_STYLES
+            .into_iter()
+            .find(|style| style.name() == name)
+            .ok_or(format!("unknown erase style: {}", name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EraseStyle::Spiral => "spiral",
+            EraseStyle::Wipe => "wipe",
+            EraseStyle::Burst => "burst",
+            EraseStyle::Instant => "instant",
+        }
+    }
+
+    // 矩形の一部を空白にする。消えきったらtrueを返す
+    pub fn step(&self, effect: &mut ErasingEffect, field: &mut Field) -> bool {
+        let r = effect.rectangle;
+        match self {
+            EraseStyle::Spiral => spiral_step(effect, field),
+            EraseStyle::Wipe => erase_some(&r, field, |remaining| {
+                let bottom = remaining.iter().map(|p| p.y).max();
+                remaining
+                    .iter()
+                    .copied()
+                    .filter(|p| Some(p.y) == bottom)
+                    .collect()
+            }),
+            EraseStyle::Burst => erase_some(&r, field, |remaining| {
+                // 中心からの距離。座標を2倍して中心が整数になるようにする
+                let ring = |p: &Point| {
+                    let dx = (2 * p.x).abs_diff(r.left + r.right);
+                    let dy = (2 * p.y).abs_diff(r.top + r.bottom);
+                    dx.max(dy)
+                };
+                let inner = remaining.iter().map(ring).min();
+                remaining
+                    .iter()
+                    .copied()
+                    .filter(|p| Some(ring(p)) == inner)
+                    .collect()
+            }),
+            EraseStyle::Instant => erase_some(&r, field, |remaining| remaining.to_vec()),
+        }
+    }
+}
+
+// 矩形に残っているERASINGのマスのうち、selectで選んだものを空白にする
+fn erase_some(r: &Rectangle, field: &mut Field, select: impl Fn(&[Point]) -> Vec<Point>) -> bool {
+    let mut remaining = Vec::new();
+    for y in r.top..=r.bottom {
+        for x in r.left..=r.right {
+            if field.cells[y][x] == ERASING {
+                remaining.push(Point::new(x, y));
+            }
+        }
+    }
+    let erased = select(&remaining);
+    for p in &erased {
+        field.cells[p.y][p.x] = EMPTY;
+    }
+    erased.len() == remaining.len()
+}
+
+// 渦巻き状に消す
+fn spiral_step(effect: &mut ErasingEffect, field: &mut Field) -> bool {
+    field.cells[effect.cursor.y][effect.cursor.x] = EMPTY;
+
+    if effect.dir == Direction::Right {
+        if effect.cursor.x + 1 < FIELD_W
+            && field.cells[effect.cursor.y][effect.cursor.x + 1] == ERASING
+        {
+            effect.cursor.x += 1;
+        } else {
+            effect.dir = Direction::Down;
+            effect.cursor.y += 1;
+        }
+    } else if effect.dir == Direction::Down {
+        if effect.cursor.y + 1 < FIELD_H
+            && field.cells[effect.cursor.y + 1][effect.cursor.x] == ERASING
+        {
+            effect.cursor.y += 1;
+        } else {
+            effect.dir = Direction::Left;
+            effect.cursor.x -= 1;
+        }
+    } else if effect.dir == Direction::Left {
+        if effect.cursor.x >= 1 && field.cells[effect.cursor.y][effect.cursor.x - 1] == ERASING {
+            effect.cursor.x -= 1;
+        } else {
+            effect.dir = Direction::Up;
+            effect.cursor.y -= 1;
+        }
+    } else if effect.dir == Direction::Up {
+        if effect.cursor.y >= 1 && field.cells[effect.cursor.y - 1][effect.cursor.x] == ERASING {
+            effect.cursor.y -= 1;
+        } else {
+            effect.dir = Direction::Right;
+            effect.cursor.x += 1;
+        }
+    }
+    field.cells[effect.cursor.y][effect.cursor.x] == EMPTY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::*;
+    use proptest::prelude::*;
+
+    // 矩形をERASINGにしたフィールドで、消えきるまでにかかった回数と得点
+    fn erase(style: EraseStyle, rectangle: Rectangle) -> (usize, i32, usize) {
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.erase_style = style;
+        for y in rectangle.top..=rectangle.bottom {
+            for x in rectangle.left..=rectangle.right {
+                game.field.cells[y][x] = ERASING;
+            }
+        }
+        game.erasing_effects.push(ErasingEffect {
+            erased_block_count: 1,
+            text: String::new(),
+            exist: true,
+            cursor: Point::new(rectangle.left, rectangle.bottom),
+            erase_wait: ERASE_WAIT,
+            dir: Direction::Up,
+            rectangle,
+            owner: 0,
+        });
+
+        let mut steps = 0;
+        let mut erased_events = 0;
+        while game.erasing_effects.iter().any(|effect| effect.exist) {
+            game.update_erasing_effects();
+            steps += 1;
+            erased_events += game
+                .events
+                .drain(..)
+                .filter(|event| matches!(event, GameEvent::Erased { .. }))
+                .count();
+            assert!(steps <= rectangle.area() * (ERASE_WAIT as usize + 1));
+        }
+        // 消えきってからは何度呼んでも得点は増えない
+        game.update_erasing_effects();
+        assert!(game.events.is_empty());
+        assert!(game.field.cells.iter().flatten().all(|&cell| cell == EMPTY));
+        (steps, game.score, erased_events)
+    }
+
+    #[test]
+    fn test_from_name() {
+        for style in ERASE_STYLES {
+            assert_eq!(EraseStyle::from_name(style.name()), Ok(style));
+        }
+        assert!(EraseStyle::from_name("fade").is_err());
+    }
+
+    #[test]
+    fn test_steps_per_style() {
+        let rectangle = Rectangle {
+            left: 2,
+            top: 3,
+            right: 6,
+            bottom: 5,
+        };
+        let wait = ERASE_WAIT as usize + 1;
+        assert_eq!(erase(EraseStyle::Spiral, rectangle).0, 15 * wait);
+        assert_eq!(erase(EraseStyle::Wipe, rectangle).0, 3 * wait);
+        // 5x3の矩形は中心、その周りの3x3、外周の3回
+        assert_eq!(erase(EraseStyle::Burst, rectangle).0, 3 * wait);
+        assert_eq!(erase(EraseStyle::Instant, rectangle).0, wait);
+    }
+
+    // 消される矩形は2x2以上
+    fn random_rectangle() -> impl Strategy<Value = Rectangle> {
+        (0..FIELD_W - 1, 0..FIELD_H - 1)
+            .prop_flat_map(|(left, top)| {
+                (Just(left), Just(top), left + 1..FIELD_W, top + 1..FIELD_H)
+            })
+            .prop_map(|(left, top, right, bottom)| Rectangle {
+                left,
+                top,
+                right,
+                bottom,
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_every_style_finishes_and_scores_once(rectangle in random_rectangle()) {
+            for style in ERASE_STYLES {
+                let (_, score, erased_events) = erase(style, rectangle);
+                prop_assert_eq!(erased_events, 1);
+                prop_assert_eq!(score, 10 * rectangle.area() as i32);
+            }
+        }
+    }
+}
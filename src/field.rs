@@ -1,3 +1,4 @@
+use std::cmp::Reverse;
 use std::collections::HashMap;
 
 pub type Cell = char;
@@ -7,7 +8,7 @@ pub const EMPTY: Cell = ' ';
 pub const FIELD_W: usize = 16;
 pub const FIELD_H: usize = 18;
 
-#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct Rectangle {
     pub left: usize,
     pub top: usize,
@@ -20,6 +21,18 @@ impl Rectangle {
         self.width() * self.height()
     }
 
+    // 消す矩形を選ぶ順番。大きいものから、同じ大きさなら上、左にあるものから
+    // HashMapから集めた矩形の順番によらず、同じフィールドなら必ず同じ矩形を選ぶ
+    pub fn erase_priority(&self) -> (Reverse<usize>, usize, usize, usize, usize) {
+        (
+            Reverse(self.area()),
+            self.top,
+            self.left,
+            self.bottom,
+            self.right,
+        )
+    }
+
     pub fn width(&self) -> usize {
         self.right - self.left + 1
     }
@@ -37,7 +50,16 @@ impl Rectangle {
     }
 }
 
-#[derive(Default, Debug)]
+// マスの各辺が別のブロック（空白を含む）と接しているか
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Borders {
+    pub top: bool,
+    pub right: bool,
+    pub bottom: bool,
+    pub left: bool,
+}
+
+#[derive(Default, Debug, Clone, Eq, PartialEq, Hash)]
 pub struct Field {
     pub cells: [[Cell; FIELD_W]; FIELD_H],
 }
@@ -49,7 +71,7 @@ impl Field {
         }
     }
 
-    #[allow(dead_code)]
+    // 足りない行や行末は空白とみなす
     pub fn from_text(cells_text: &str) -> Field {
         let mut field = Field {
             cells: [[' '; FIELD_W]; FIELD_H],
@@ -57,7 +79,7 @@ impl Field {
 
         let lines: Vec<&str> = cells_text.lines().collect();
         for y in 0..FIELD_H {
-            let line = lines[y].to_string();
+            let line = lines.get(y).unwrap_or(&"").to_string();
             for x in 0..FIELD_W {
                 if let Some(ch) = line.chars().nth(x) {
                     field.cells[y][x] = ch;
@@ -70,6 +92,14 @@ impl Field {
         field
     }
 
+    // from_textで読める形式にする。行末の空白は省く
+    pub fn to_text(&self) -> String {
+        self.cells
+            .iter()
+            .map(|row| row.iter().collect::<String>().trim_end().to_string() + "\n")
+            .collect()
+    }
+
     #[allow(dead_code)]
     pub fn print_with_coord(&self) {
         print!("  ");
@@ -96,6 +126,17 @@ impl Field {
         }
     }
 
+    // ブロックの境目を描くために使う。フィールドの外とは接していないものとする
+    pub fn borders(&self, x: usize, y: usize) -> Borders {
+        let cell = self.cells[y][x];
+        Borders {
+            top: y > 0 && self.cells[y - 1][x] != cell,
+            right: x + 1 < FIELD_W && self.cells[y][x + 1] != cell,
+            bottom: y + 1 < FIELD_H && self.cells[y + 1][x] != cell,
+            left: x > 0 && self.cells[y][x - 1] != cell,
+        }
+    }
+
     pub fn is_rectangle(&self, left: usize, top: usize, right: usize, bottom: usize) -> bool {
         // 幅または高さが1の矩形は除外
         if !(left < right && top < bottom) {
@@ -200,7 +241,7 @@ impl Field {
 
     pub fn find_rectangle_to_be_erased(&self, x: usize, y: usize) -> Option<Rectangle> {
         let mut rectangles = self.find_all_rectangles();
-        rectangles.sort_by(|a, b| b.area().cmp(&a.area()));
+        rectangles.sort_by_key(|r| r.erase_priority());
         for r in &rectangles {
             // 矩形が最後に着弾した位置を含むなら
             // これがないと、初期配置ですでに矩形を成しているところがフィールドに表示されると同時に消えてしまう
@@ -215,6 +256,244 @@ impl Field {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    const BLOCK_IDS: &str = "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+    // 生成したフィールドと、その中に置いた完全な矩形のリスト
+    #[derive(Debug)]
+    struct GeneratedField {
+        field: Field,
+        rectangles: Vec<Rectangle>,
+    }
+
+    fn overlaps(field: &Field, r: &Rectangle) -> bool {
+        if r.right >= FIELD_W || r.bottom >= FIELD_H {
+            return true;
+        }
+        for y in r.top..=r.bottom {
+            for x in r.left..=r.right {
+                if field.cells[y][x] != EMPTY {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn fill(field: &mut Field, r: &Rectangle, ch: Cell) {
+        for y in r.top..=r.bottom {
+            for x in r.left..=r.right {
+                field.cells[y][x] = ch;
+            }
+        }
+    }
+
+    // 矩形を縦横に再帰的に分割してブロックを作る
+    fn split(
+        r: Rectangle,
+        decisions: &mut impl Iterator<Item = u8>,
+        depth: usize,
+    ) -> Vec<Rectangle> {
+        let decision = decisions.next().unwrap_or(0);
+        if depth == 0 || decision.is_multiple_of(3) {
+            return vec![r];
+        }
+        let (first, second) = if decision % 3 == 1 && r.width() >= 2 {
+            let at = r.left + (decision as usize / 3) % (r.width() - 1);
+            (
+                Rectangle { right: at, ..r },
+                Rectangle { left: at + 1, ..r },
+            )
+        } else if r.height() >= 2 {
+            let at = r.top + (decision as usize / 3) % (r.height() - 1);
+            (
+                Rectangle { bottom: at, ..r },
+                Rectangle { top: at + 1, ..r },
+            )
+        } else {
+            return vec![r];
+        };
+        let mut blocks = split(first, decisions, depth - 1);
+        blocks.extend(split(second, decisions, depth - 1));
+        blocks
+    }
+
+    fn generated_field() -> impl Strategy<Value = GeneratedField> {
+        let region = (
+            0..FIELD_W,
+            0..FIELD_H,
+            2..=7usize,
+            2..=7usize,
+            prop::collection::vec(any::<u8>(), 7),
+        );
+        let noise = (0..FIELD_W, 0..FIELD_H, 1..=3usize, 1..=3usize);
+        (
+            prop::collection::vec(region, 1..6),
+            prop::collection::vec(noise, 0..8),
+        )
+            .prop_map(|(regions, noises)| {
+                let mut field = Field::new();
+                let mut rectangles = Vec::new();
+                let mut ids = BLOCK_IDS.chars();
+
+                for (left, top, w, h, decisions) in regions {
+                    let r = Rectangle {
+                        left,
+                        top,
+                        right: left + w - 1,
+                        bottom: top + h - 1,
+                    };
+                    if overlaps(&field, &r) {
+                        continue;
+                    }
+                    for block in split(r, &mut decisions.into_iter(), 3) {
+                        fill(&mut field, &block, ids.next().unwrap());
+                    }
+                    rectangles.push(r);
+                }
+
+                for (left, top, w, h) in noises {
+                    let r = Rectangle {
+                        left,
+                        top,
+                        right: left + w - 1,
+                        bottom: top + h - 1,
+                    };
+                    if overlaps(&field, &r) {
+                        continue;
+                    }
+                    fill(&mut field, &r, ids.next().unwrap());
+                }
+
+                GeneratedField { field, rectangles }
+            })
+    }
+
+    // 各ブロックの外接矩形
+    fn block_bounds(field: &Field) -> HashMap<Cell, Rectangle> {
+        let mut bounds: HashMap<Cell, Rectangle> = HashMap::new();
+        for y in 0..FIELD_H {
+            for x in 0..FIELD_W {
+                let ch = field.cells[y][x];
+                if ch == EMPTY {
+                    continue;
+                }
+                let b = bounds.entry(ch).or_insert(Rectangle {
+                    left: x,
+                    top: y,
+                    right: x,
+                    bottom: y,
+                });
+                b.left = b.left.min(x);
+                b.top = b.top.min(y);
+                b.right = b.right.max(x);
+                b.bottom = b.bottom.max(y);
+            }
+        }
+        bounds
+    }
+
+    // 矩形の定義を愚直に判定する: 辺がすべて埋まっていて、辺上のブロックが外にはみ出していない
+    fn is_complete(field: &Field, bounds: &HashMap<Cell, Rectangle>, r: &Rectangle) -> bool {
+        if !(r.left < r.right && r.top < r.bottom) {
+            return false;
+        }
+        for y in r.top..=r.bottom {
+            for x in r.left..=r.right {
+                if !(x == r.left || x == r.right || y == r.top || y == r.bottom) {
+                    continue;
+                }
+                let ch = field.cells[y][x];
+                if ch == EMPTY {
+                    return false;
+                }
+                let b = bounds[&ch];
+                if b.left < r.left || b.top < r.top || b.right > r.right || b.bottom > r.bottom {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    proptest! {
+        #[test]
+        fn prop_generated_rectangles_are_found(g in generated_field()) {
+            let found = g.field.find_all_rectangles();
+            for r in &g.rectangles {
+                prop_assert!(found.contains(r), "{:?} not found in {:?}", r, found);
+            }
+        }
+
+        #[test]
+        fn prop_found_rectangles_are_complete(g in generated_field()) {
+            let bounds = block_bounds(&g.field);
+            for r in g.field.find_all_rectangles() {
+                prop_assert!(is_complete(&g.field, &bounds, &r), "{:?} is not complete", r);
+            }
+        }
+
+        #[test]
+        fn prop_erased_rectangle_is_largest(
+            g in generated_field(),
+            x in 0..FIELD_W,
+            y in 0..FIELD_H,
+        ) {
+            let bounds = block_bounds(&g.field);
+            let mut largest: Option<usize> = None;
+            for left in 0..=x {
+                for right in x..FIELD_W {
+                    for top in 0..=y {
+                        for bottom in y..FIELD_H {
+                            let r = Rectangle { left, top, right, bottom };
+                            if is_complete(&g.field, &bounds, &r) && largest < Some(r.area()) {
+                                largest = Some(r.area());
+                            }
+                        }
+                    }
+                }
+            }
+
+            let erased = g.field.find_rectangle_to_be_erased(x, y);
+            prop_assert_eq!(erased.map(|r| r.area()), largest);
+            if let Some(r) = erased {
+                prop_assert!(r.left <= x && x <= r.right && r.top <= y && y <= r.bottom);
+                prop_assert!(is_complete(&g.field, &bounds, &r));
+            }
+        }
+    }
+
+    #[test]
+    fn test_borders() {
+        #[rustfmt::skip]
+        let field = Field::from_text(concat!(
+            "aab\n",
+            "aab\n",
+            " cc\n",
+        ));
+        assert_eq!(field.borders(0, 0), Borders::default());
+        assert_eq!(
+            field.borders(1, 1),
+            Borders {
+                top: false,
+                right: true,
+                bottom: true,
+                left: false,
+            }
+        );
+        assert_eq!(
+            field.borders(2, 2),
+            Borders {
+                top: true,
+                right: true,
+                bottom: true,
+                left: false,
+            }
+        );
+        // 空白のマスも隣のブロックとの境目を持つ
+        assert!(field.borders(0, 2).top && field.borders(0, 2).right);
+    }
 
     #[test]
     fn test_check_erase_row() {
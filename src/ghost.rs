@@ -0,0 +1,82 @@
+use crate::model::*;
+use crate::replay::*;
+
+// 過去のリプレイを今のゲームと並べて動かす「ゴースト」
+// 記録されたコマンドを1フレームずつ与えるだけで、音やイベントは捨てる
+#[derive(Debug, Clone)]
+pub struct Ghost {
+    pub game: Game,
+    replay: Replay,
+    stage: String,
+}
+
+impl Ghost {
+    pub fn load(filename: &str, stage: &str) -> Result<Ghost, String> {
+        Ghost::new(Replay::load(filename)?, stage)
+    }
+
+    pub fn new(replay: Replay, stage: &str) -> Result<Ghost, String> {
+        Ok(Ghost {
+            game: replay.new_game(stage)?,
+            replay,
+            stage: stage.to_string(),
+        })
+    }
+
+    // 最初からやり直す（今のゲームをやり直したとき）
+    pub fn reset(&mut self) {
+        self.game = self
+            .replay
+            .new_game(&self.stage)
+            .expect("replay was loaded once");
+    }
+
+    // 記録が尽きたらCommand::Noneで進める
+    pub fn update(&mut self) {
+        let frame = (self.game.frame + 1) as usize;
+        let commands = self.replay.commands.get(frame).cloned().unwrap_or_default();
+        self.game.update_players(&commands);
+        self.game.requested_sounds.clear();
+        self.game.events.clear();
+    }
+
+    // 今のゲームがゴーストより何点上回っているか
+    pub fn score_delta(&self, game: &Game) -> i32 {
+        game.score - self.game.score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::testing::*;
+
+    #[test]
+    fn test_ghost_follows_recorded_run() {
+        let (mut best, log) = recording_game(9);
+        let pattern = [
+            Command::Shoot,
+            Command::Right,
+            Command::Shoot,
+            Command::None,
+        ];
+        play_pattern(&mut best, &pattern, 200);
+
+        let mut ghost = Ghost::new(log.replay(), STAGE).unwrap();
+        for _ in 0..200 {
+            ghost.update();
+        }
+        assert_eq!(ghost.game.state_hash(), best.state_hash());
+        assert_eq!(ghost.score_delta(&best), 0);
+
+        // 記録より長く動かしても止まらない
+        for _ in 0..10 {
+            ghost.update();
+        }
+        assert_eq!(ghost.game.frame, 209);
+
+        ghost.reset();
+        assert_eq!(ghost.game.frame, -1);
+        assert!(ghost.game.requested_sounds.is_empty());
+    }
+}
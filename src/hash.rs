@@ -0,0 +1,57 @@
+use std::hash::Hasher;
+
+// FNV-1a (64bit)
+// std::collections::hash_map::DefaultHasherはRustのバージョンで結果が変わりうるので、
+// ネットワーク越しやファイルに保存して比べるハッシュにはこちらを使う
+#[derive(Debug, Clone)]
+pub struct StateHasher {
+    hash: u64,
+}
+
+impl StateHasher {
+    pub fn new() -> StateHasher {
+        StateHasher {
+            hash: 0xcbf29ce484222325,
+        }
+    }
+}
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        StateHasher::new()
+    }
+}
+
+impl Hasher for StateHasher {
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.hash ^= *byte as u64;
+            self.hash = self.hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    // プラットフォームによらず同じ値になるように、usizeは64bitとして扱う
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_i64(i as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a() {
+        let mut hasher = StateHasher::new();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63dc4c8601ec8c);
+    }
+}
@@ -0,0 +1,230 @@
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::model::FPS;
+
+// 1画素3バイト（RGB）を左上から並べた画像
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RgbImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbImage {
+    pub fn new(width: u32, height: u32, pixels: Vec<u8>) -> Result<RgbImage, String> {
+        if pixels.len() != (width * height * 3) as usize {
+            return Err(format!(
+                "{} bytes of pixels for {}x{} image",
+                pixels.len(),
+                width,
+                height
+            ));
+        }
+        Ok(RgbImage {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn save_png(&self, filename: &str) -> Result<(), String> {
+        let file = File::create(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels))
+            .map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    // 最近傍法で拡大・縮小する
+    pub fn scale(&self, factor: f32) -> RgbImage {
+        let width = ((self.width as f32 * factor).round() as u32).max(1);
+        let height = ((self.height as f32 * factor).round() as u32).max(1);
+        let mut pixels = Vec::with_capacity((width * height * 3) as usize);
+        for y in 0..height {
+            let src_y = (y * self.height / height) as usize;
+            for x in 0..width {
+                let src_x = (x * self.width / width) as usize;
+                let i = (src_y * self.width as usize + src_x) * 3;
+                pixels.extend_from_slice(&self.pixels[i..i + 3]);
+            }
+        }
+        RgbImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+// 1画素4バイト（RGBA）の画像。スキンのスプライトシートなど
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    // 8ビットのRGBAに揃えて読み込む
+    pub fn load_png(filename: &str) -> Result<RgbaImage, String> {
+        let file = File::open(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder
+            .read_info()
+            .map_err(|e| format!("{}: {}", filename, e))?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buf)
+            .map_err(|e| format!("{}: {}", filename, e))?;
+        let buf = &buf[..info.buffer_size()];
+
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => buf.to_vec(),
+            png::ColorType::Rgb => buf
+                .chunks(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => buf.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+            png::ColorType::Indexed => unreachable!("expanded by normalize_to_color8"),
+        };
+        Ok(RgbaImage {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+}
+
+// ゲームの1フレームを1コマとするアニメーションGIF
+pub struct GifWriter {
+    encoder: gif::Encoder<BufWriter<File>>,
+    filename: String,
+    width: u32,
+    height: u32,
+    frames: u32,
+}
+
+impl GifWriter {
+    pub fn create(filename: &str, width: u32, height: u32) -> Result<GifWriter, String> {
+        if width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(format!("{}x{} is too large for GIF", width, height));
+        }
+        let file = File::create(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        let mut encoder = gif::Encoder::new(BufWriter::new(file), width as u16, height as u16, &[])
+            .map_err(|e| format!("{}: {}", filename, e))?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(|e| format!("{}: {}", filename, e))?;
+        Ok(GifWriter {
+            encoder,
+            filename: filename.to_string(),
+            width,
+            height,
+            frames: 0,
+        })
+    }
+
+    pub fn add_frame(&mut self, image: &RgbImage) -> Result<(), String> {
+        if (image.width, image.height) != (self.width, self.height) {
+            return Err(format!(
+                "{}x{} frame for {}x{} GIF",
+                image.width, image.height, self.width, self.height
+            ));
+        }
+        let mut frame =
+            gif::Frame::from_rgb_speed(image.width as u16, image.height as u16, &image.pixels, 10);
+        frame.delay = frame_delay(self.frames);
+        self.frames += 1;
+        self.encoder
+            .write_frame(&frame)
+            .map_err(|e| format!("{}: {}", self.filename, e))
+    }
+}
+
+// GIFの表示時間は1/100秒単位なので、端数を繰り越してFPSに合わせる
+fn frame_delay(frame: u32) -> u16 {
+    let centis = |frame: u32| (frame * 100 + FPS as u32 / 2) / FPS as u32;
+    (centis(frame + 1) - centis(frame)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale() {
+        let image = RgbImage::new(2, 1, vec![1, 2, 3, 4, 5, 6]).unwrap();
+        let scaled = image.scale(2.0);
+        assert_eq!((scaled.width, scaled.height), (4, 2));
+        assert_eq!(&scaled.pixels[..12], &[1, 2, 3, 1, 2, 3, 4, 5, 6, 4, 5, 6]);
+        assert_eq!(scaled.pixels[..12], scaled.pixels[12..]);
+        assert_eq!(scaled.scale(0.5), image);
+    }
+
+    #[test]
+    fn test_write_gif() {
+        let path = std::env::temp_dir().join(format!("image-{}.gif", std::process::id()));
+        let mut writer = GifWriter::create(path.to_str().unwrap(), 2, 2).unwrap();
+        for i in 0..FPS as u8 {
+            let image = RgbImage::new(2, 2, vec![i * 8; 12]).unwrap();
+            writer.add_frame(&image).unwrap();
+        }
+        assert!(writer
+            .add_frame(&RgbImage::new(1, 1, vec![0; 3]).unwrap())
+            .is_err());
+        drop(writer);
+
+        let mut decoder = gif::DecodeOptions::new();
+        decoder.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = decoder.read_info(File::open(&path).unwrap()).unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!(frame.buffer[..3], [delays.len() as u8 * 8; 3]);
+            delays.push(frame.delay);
+        }
+        std::fs::remove_file(&path).ok();
+        assert_eq!(delays.len(), FPS as usize);
+        // 1秒分のフレームの表示時間の合計はちょうど1秒
+        assert_eq!(delays.iter().sum::<u16>(), 100);
+    }
+
+    #[test]
+    fn test_load_png_as_rgba() {
+        let image = RgbImage::new(2, 1, vec![10, 20, 30, 40, 50, 60]).unwrap();
+        let path = std::env::temp_dir().join(format!("image-rgba-{}.png", std::process::id()));
+        image.save_png(path.to_str().unwrap()).unwrap();
+        let loaded = RgbaImage::load_png(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!((loaded.width, loaded.height), (2, 1));
+        assert_eq!(loaded.pixels, vec![10, 20, 30, 255, 40, 50, 60, 255]);
+    }
+
+    #[test]
+    fn test_save_png() {
+        let pixels = vec![
+            255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 9, 9, 9,
+        ];
+        let image = RgbImage::new(3, 2, pixels.clone()).unwrap();
+        assert!(RgbImage::new(3, 3, pixels.clone()).is_err());
+
+        let path = std::env::temp_dir().join(format!("image-{}.png", std::process::id()));
+        image.save_png(path.to_str().unwrap()).unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!(&buf[..info.buffer_size()], &pixels[..]);
+    }
+}
@@ -0,0 +1,184 @@
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+
+use crate::replay::*;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// 署名に使う鍵を渡す環境変数
+pub const SIGNING_KEY_VAR: &str = "QUARTH_SIGNING_KEY";
+
+// リプレイを再生して確かめた結果
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Summary {
+    pub score: i32,
+    pub frames: i32,
+    pub is_clear: bool,
+    pub seed: u64,
+    pub stage_hash: u64,
+}
+
+impl Summary {
+    // 署名する内容。項目の順番を変えると以前の署名が通らなくなる
+    fn message(&self) -> String {
+        format!(
+            "score={};frames={};clear={};seed={};stage={:016x}",
+            self.score, self.frames, self.is_clear, self.seed, self.stage_hash
+        )
+    }
+
+    pub fn sign(&self, key: &[u8]) -> String {
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(self.message().as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    pub fn verify_signature(&self, key: &[u8], signature: &str) -> bool {
+        let Some(bytes) = decode_hex(signature) else {
+            return false;
+        };
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(self.message().as_bytes());
+        mac.verify_slice(&bytes).is_ok()
+    }
+
+    pub fn to_json(&self, key: &[u8]) -> serde_json::Value {
+        json!({
+            "score": self.score,
+            "frames": self.frames,
+            "is_clear": self.is_clear,
+            "seed": self.seed,
+            "stage": format!("{:016x}", self.stage_hash),
+            "signature": self.sign(key),
+        })
+    }
+}
+
+// 記録されたシードとステージで再生し直し、ゲームオーバーかクリアまでの結果を返す
+// ステージが違う、途中のハッシュが食い違う、ゲームが終わっていない、
+// 練習モードで巻き戻した場合は受け付けない
+pub fn verify_replay(replay: &Replay, stage: &str) -> Result<Summary, String> {
+    let seed = replay.seed.ok_or("replay has no seed")?;
+    let recorded_stage = replay.stage_hash.ok_or("replay has no stage hash")?;
+    if !replay.rewinds.is_empty() {
+        return Err("replay was rewound in practice mode".to_string());
+    }
+
+    let game = replay.new_game(stage)?;
+    let stage_hash = game.stage_hash();
+    if stage_hash != recorded_stage {
+        return Err(format!(
+            "stage differs (recorded {:016x}, {} is {:016x})",
+            recorded_stage, stage, stage_hash
+        ));
+    }
+
+    replay
+        .verify(game.clone())
+        .map_err(|desync| desync.to_string())?;
+
+    let mut game = game;
+    for commands in &replay.commands {
+        if game.is_over || game.is_clear {
+            break;
+        }
+        game.update_players(commands);
+    }
+    if !game.is_over && !game.is_clear {
+        return Err(format!(
+            "game is not finished after {} frames",
+            game.frame + 1
+        ));
+    }
+
+    Ok(Summary {
+        score: game.score,
+        frames: game.frame + 1,
+        is_clear: game.is_clear,
+        seed,
+        stage_hash,
+    })
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::*;
+    use crate::replay::testing::*;
+
+    fn record_until_over() -> Replay {
+        let (mut game, log) = recording_game(5);
+        game.hash_interval = 60;
+        let pattern = [
+            Command::Shoot,
+            Command::Left,
+            Command::Shoot,
+            Command::Right,
+        ];
+        play_until_finished(&mut game, &pattern);
+        log.replay()
+    }
+
+    #[test]
+    fn test_signed_summary() {
+        let replay = record_until_over();
+        let summary = verify_replay(&replay, STAGE).unwrap();
+        assert_eq!(summary.seed, 5);
+        assert_eq!(summary.frames as usize, replay.commands.len());
+        assert!(!summary.is_clear);
+
+        let signature = summary.sign(b"secret");
+        assert_eq!(signature.len(), 64);
+        assert!(summary.verify_signature(b"secret", &signature));
+        assert!(!summary.verify_signature(b"other", &signature));
+        let edited = Summary {
+            score: summary.score + 100,
+            ..summary
+        };
+        assert!(!edited.verify_signature(b"secret", &signature));
+    }
+
+    #[test]
+    fn test_rejects_tampered_replay() {
+        let replay = record_until_over();
+
+        let mut other_stage = replay.clone();
+        other_stage.stage_hash = Some(0);
+        assert!(verify_replay(&other_stage, STAGE)
+            .unwrap_err()
+            .starts_with("stage differs"));
+
+        let mut edited = replay.clone();
+        for commands in &mut edited.commands[10..50] {
+            *commands = vec![Command::Up];
+        }
+        assert!(verify_replay(&edited, STAGE)
+            .unwrap_err()
+            .starts_with("hash differs"));
+
+        let mut practice = replay.clone();
+        practice.rewinds.push(100);
+        assert!(verify_replay(&practice, STAGE)
+            .unwrap_err()
+            .contains("practice"));
+
+        let mut truncated = replay.clone();
+        truncated.commands.truncate(10);
+        assert!(verify_replay(&truncated, STAGE).is_err());
+    }
+}
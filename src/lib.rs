@@ -0,0 +1,23 @@
+pub mod bitboard;
+pub mod bot;
+pub mod cache;
+pub mod effects;
+pub mod env;
+pub mod erase;
+pub mod field;
+pub mod ghost;
+pub mod hash;
+pub mod image;
+pub mod leaderboard;
+pub mod model;
+pub mod netplay;
+pub mod palette;
+pub mod recorder;
+pub mod replay;
+pub mod rewind;
+pub mod scenario;
+pub mod skin;
+pub mod spectator;
+pub mod terminal;
+pub mod versus;
+pub mod view;
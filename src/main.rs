@@ -1,18 +1,35 @@
+use rust_quarth::bot::*;
+use rust_quarth::cache::*;
+use rust_quarth::effects::*;
+use rust_quarth::erase::*;
+use rust_quarth::field::*;
+use rust_quarth::ghost::*;
+use rust_quarth::image::*;
+use rust_quarth::leaderboard::{self, SIGNING_KEY_VAR};
+use rust_quarth::model::{self, *};
+use rust_quarth::netplay::*;
+use rust_quarth::palette::*;
+use rust_quarth::recorder::*;
+use rust_quarth::replay::*;
+use rust_quarth::rewind::*;
+use rust_quarth::skin::*;
+use rust_quarth::spectator::*;
+use rust_quarth::terminal::*;
+use rust_quarth::versus::*;
+use rust_quarth::view::*;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::keyboard::Scancode;
 use sdl2::mixer;
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
-use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
+use sdl2::render::{BlendMode, Canvas, RenderTarget, Texture, TextureCreator};
+use sdl2::surface::Surface;
 use sdl2::ttf::Sdl2TtfContext;
 use sdl2::video::{Window, WindowContext};
 use std::collections::HashMap;
 use std::fs::{self};
 use std::time::{Duration, SystemTime};
-mod field;
-mod model;
-use crate::field::*;
-use crate::model::*;
 
 pub const WINDOW_TITLE: &str = "rust-rectangle-eraser";
 pub const SCREEN_WIDTH: i32 = CELL_SIZE * FIELD_W as i32 + INFO_WIDTH;
@@ -45,18 +62,358 @@ impl<'a> Image<'a> {
     }
 }
 
-struct Resources<'a> {
+// スキンの定義と、読み込んだスプライトシート
+struct SkinSheet<'a> {
+    skin: Skin,
+    texture: Texture<'a>,
+}
+
+impl SkinSheet<'_> {
+    // タイルに色を掛けて描く
+    fn draw<T: RenderTarget>(
+        &mut self,
+        canvas: &mut Canvas<T>,
+        tile: Tile,
+        (r, g, b): (u8, u8, u8),
+        dst: Rect,
+    ) -> Result<(), String> {
+        self.texture.set_color_mod(r, g, b);
+        canvas.copy(&self.texture, Rect::from(tile), dst)
+    }
+}
+
+struct Resources<'a, C = WindowContext> {
     images: HashMap<String, Image<'a>>,
     chunks: HashMap<String, sdl2::mixer::Chunk>,
     fonts: HashMap<String, sdl2::ttf::Font<'a, 'a>>,
+    // (フォント, 文字列, 色)ごとの文字のテクスチャ
+    texts: FrameCache<(&'static str, String, Color), Texture<'a>>,
+    texture_creator: &'a TextureCreator<C>,
+    skin: Option<SkinSheet<'a>>, // Noneなら色で塗りつぶして描く
+}
+
+struct Options {
+    command_log: Option<String>, // Noneならコマンドログを記録しない
+    bot_address: Option<String>, // ボットの接続を待ち受けるアドレス
+    lockstep: bool,
+    headless: bool,
+    spectate: Option<String>, // 観戦用の状態を書き出す先
+    versus: bool,
+    coop: bool,
+    host: Option<String>, // ネットプレイで相手の接続を待ち受けるアドレス
+    join: Option<String>, // ネットプレイで接続するホストのアドレス
+    input_delay: usize,
+    hash_interval: i32,     // コマンドログに状態のハッシュを書くフレーム間隔
+    verify: Option<String>, // ハッシュを確かめるリプレイ
+    verify_replay: Option<String>, // 再生し直して署名付きの結果を出すリプレイ
+    ghost: Option<String>,  // ゴーストとして並べて動かすリプレイ
+    practice: bool,         // Backspaceで巻き戻せる練習モード
+    terminal: bool,         // ウィンドウを開かずに端末で遊ぶ
+    render_frame: bool,     // リプレイの1フレームを画像に書き出す
+    replay: Option<String>,
+    frame: Option<i32>,
+    output: Option<String>,
+    export_replay: bool, // リプレイをGIFか連番のPNGに書き出す
+    from: Option<i32>,
+    to: Option<i32>,
+    scale: f32,
+    palette: String,                 // resources/paletteにあるブロックの配色
+    glyphs: bool,                    // ブロックに色ごとの記号も描く
+    skin: Option<String>,            // resources/skinにあるスプライトシート。Noneなら色で塗りつぶす
+    erase_style: Option<EraseStyle>, // Noneならステージの設定に従う
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        command_log: Some("command.log".to_string()),
+        bot_address: None,
+        lockstep: false,
+        headless: false,
+        spectate: None,
+        versus: false,
+        coop: false,
+        host: None,
+        join: None,
+        input_delay: 2,
+        hash_interval: 0,
+        verify: None,
+        verify_replay: None,
+        ghost: None,
+        practice: false,
+        terminal: false,
+        render_frame: false,
+        replay: None,
+        frame: None,
+        output: None,
+        export_replay: false,
+        from: None,
+        to: None,
+        scale: 1.0,
+        palette: "default".to_string(),
+        glyphs: false,
+        skin: None,
+        erase_style: None,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => {
+                let filename = args.next().ok_or("--record requires a filename")?;
+                options.command_log = Some(filename);
+            }
+            "--no-record" => options.command_log = None,
+            "--bot" => {
+                let address = args.next().ok_or("--bot requires an address")?;
+                options.bot_address = Some(address);
+            }
+            "--lockstep" => options.lockstep = true,
+            "--headless" => options.headless = true,
+            "--versus" => options.versus = true,
+            "--coop" => options.coop = true,
+            "--practice" => options.practice = true,
+            "--terminal" => options.terminal = true,
+            "--glyphs" => options.glyphs = true,
+            "--spectate" => {
+                let target = args.next().ok_or("--spectate requires a target")?;
+                options.spectate = Some(target);
+            }
+            "--host" => {
+                let address = args.next().ok_or("--host requires an address")?;
+                options.host = Some(address);
+            }
+            "--join" => {
+                let address = args.next().ok_or("--join requires an address")?;
+                options.join = Some(address);
+            }
+            "--input-delay" => {
+                let frames = args.next().ok_or("--input-delay requires a number")?;
+                options.input_delay = frames
+                    .parse()
+                    .map_err(|_| format!("invalid input delay: {}", frames))?;
+            }
+            "--hash-interval" => {
+                let frames = args.next().ok_or("--hash-interval requires a number")?;
+                options.hash_interval = frames
+                    .parse()
+                    .ok()
+                    .filter(|frames| *frames > 0)
+                    .ok_or(format!("invalid hash interval: {}", frames))?;
+            }
+            "--verify" => {
+                let filename = args.next().ok_or("--verify requires a filename")?;
+                options.verify = Some(filename);
+            }
+            "--ghost" => {
+                let filename = args.next().ok_or("--ghost requires a filename")?;
+                options.ghost = Some(filename);
+            }
+            "--replay" => {
+                let filename = args.next().ok_or("--replay requires a filename")?;
+                options.replay = Some(filename);
+            }
+            "--frame" => {
+                let frame = args.next().ok_or("--frame requires a number")?;
+                options.frame = Some(
+                    frame
+                        .parse()
+                        .ok()
+                        .filter(|frame| *frame >= 0)
+                        .ok_or(format!("invalid frame: {}", frame))?,
+                );
+            }
+            "--output" => {
+                let filename = args.next().ok_or("--output requires a filename")?;
+                options.output = Some(filename);
+            }
+            "--from" | "--to" => {
+                let frame = args.next().ok_or(format!("{} requires a number", arg))?;
+                let frame = frame
+                    .parse()
+                    .ok()
+                    .filter(|frame| *frame >= 0)
+                    .ok_or(format!("invalid frame: {}", frame))?;
+                if arg == "--from" {
+                    options.from = Some(frame);
+                } else {
+                    options.to = Some(frame);
+                }
+            }
+            "--scale" => {
+                let scale = args.next().ok_or("--scale requires a number")?;
+                options.scale = scale
+                    .parse()
+                    .ok()
+                    .filter(|scale: &f32| *scale > 0.0 && *scale <= 8.0)
+                    .ok_or(format!("invalid scale: {}", scale))?;
+            }
+            "--palette" => {
+                let name = args.next().ok_or("--palette requires a name")?;
+                options.palette = name;
+            }
+            "--skin" => {
+                let name = args.next().ok_or("--skin requires a name")?;
+                options.skin = Some(name);
+            }
+            "--erase-style" => {
+                let name = args.next().ok_or("--erase-style requires a name")?;
+                options.erase_style = Some(EraseStyle::from_name(&name)?);
+            }
+            "render-frame" => options.render_frame = true,
+            "export-replay" => options.export_replay = true,
+            "verify-replay" => {
+                let filename = args.next().ok_or("verify-replay requires a filename")?;
+                options.verify_replay = Some(filename);
+            }
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+    }
+
+    if options.bot_address.is_none() && (options.lockstep || options.headless) {
+        return Err("--lockstep and --headless require --bot".to_string());
+    }
+    if options.versus && (options.bot_address.is_some() || options.spectate.is_some()) {
+        return Err("--versus cannot be combined with --bot or --spectate".to_string());
+    }
+    if options.versus && options.coop {
+        return Err("--versus cannot be combined with --coop".to_string());
+    }
+    if options.host.is_some() || options.join.is_some() {
+        if options.host.is_some() && options.join.is_some() {
+            return Err("--host cannot be combined with --join".to_string());
+        }
+        if options.versus || options.coop || options.bot_address.is_some() {
+            return Err("netplay cannot be combined with --versus, --coop or --bot".to_string());
+        }
+    }
+    if options.ghost.is_some() && (options.versus || options.headless) {
+        return Err("--ghost cannot be combined with --versus or --headless".to_string());
+    }
+    if options.ghost.is_some() && (options.host.is_some() || options.join.is_some()) {
+        return Err("--ghost cannot be combined with netplay".to_string());
+    }
+    if options.practice && (options.versus || options.headless || options.ghost.is_some()) {
+        return Err(
+            "--practice cannot be combined with --versus, --headless or --ghost".to_string(),
+        );
+    }
+    if options.practice && (options.host.is_some() || options.join.is_some()) {
+        return Err("--practice cannot be combined with netplay".to_string());
+    }
+    if options.terminal
+        && (options.versus
+            || options.coop
+            || options.practice
+            || options.bot_address.is_some()
+            || options.host.is_some()
+            || options.join.is_some())
+    {
+        return Err("--terminal supports single-player games only".to_string());
+    }
+    if options.render_frame && (options.replay.is_none() || options.frame.is_none()) {
+        return Err("render-frame requires --replay and --frame".to_string());
+    }
+    if options.export_replay && options.replay.is_none() {
+        return Err("export-replay requires --replay".to_string());
+    }
+
+    Ok(options)
+}
+
+fn create_recorder(options: &Options) -> Result<Box<dyn Recorder>, String> {
+    Ok(match &options.command_log {
+        Some(filename) => Box::new(
+            FileRecorder::create(filename)
+                .map_err(|e| format!("cannot create {}: {}", filename, e))?,
+        ),
+        None => Box::new(NullRecorder),
+    })
+}
+
+fn new_game(options: &Options) -> Result<Game, String> {
+    let mut game = Game::new(create_recorder(options)?);
+    game.hash_interval = options.hash_interval;
+    if options.coop {
+        game.set_player_count(2);
+    }
+    if let Some(style) = options.erase_style {
+        game.set_erase_style(style);
+    }
+    Ok(game)
+}
+
+fn new_block_colors(options: &Options) -> Result<BlockColors, String> {
+    let mut colors = BlockColors::new(Palette::load(&options.palette)?);
+    colors.glyphs = options.glyphs;
+    Ok(colors)
+}
+
+// 対戦モードでは両者同じステージ・同じシードで始め、コマンドは記録しない
+fn new_versus() -> Versus {
+    let seed = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("SystemTime before UNIX EPOCH!")
+        .as_secs();
+    let new_game = || {
+        let mut game = Game::with_seed(seed, Box::new(NullRecorder));
+        game.load_stage("resources/data/stage1.dat");
+        game
+    };
+    Versus::new([new_game(), new_game()])
 }
 
 pub fn main() -> Result<(), String> {
+    let options = parse_args()?;
+
+    if let Some(filename) = &options.verify {
+        return check_replay_hashes(filename);
+    }
+    if let Some(filename) = &options.verify_replay {
+        return sign_replay(filename);
+    }
+    if options.render_frame {
+        return render_replay_frame(&options);
+    }
+    if options.export_replay {
+        return export_replay(&options);
+    }
+
+    let mut bot = match &options.bot_address {
+        Some(address) => Some(BotServer::listen(address, options.lockstep)?),
+        None => None,
+    };
+
+    let mut spectator = match &options.spectate {
+        Some(target) => Some(Spectator::open(target)?),
+        None => None,
+    };
+
+    if options.headless {
+        return run_headless(&options, bot, spectator);
+    }
+    if options.terminal {
+        return run_terminal(&options, spectator);
+    }
+
+    // 相手とつながってからウィンドウを開く
+    let netplay = if let Some(address) = &options.host {
+        Some(Netplay::host(address, options.input_delay)?)
+    } else if let Some(address) = &options.join {
+        Some(Netplay::join(address)?)
+    } else {
+        None
+    };
+
     let sdl_context = sdl2::init()?;
 
     let video_subsystem = sdl_context.video()?;
+    let window_width = if options.versus {
+        SCREEN_WIDTH * 2
+    } else {
+        SCREEN_WIDTH
+    };
     let window = video_subsystem
-        .window(WINDOW_TITLE, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)
+        .window(WINDOW_TITLE, window_width as u32, SCREEN_HEIGHT as u32)
         .position_centered()
         .opengl()
         .build()
@@ -74,16 +431,59 @@ pub fn main() -> Result<(), String> {
     canvas.set_blend_mode(BlendMode::Blend);
 
     let texture_creator = canvas.texture_creator();
-    let mut resources = load_resources(&texture_creator, &mut canvas, &ttf_context);
+    let mut resources = load_resources(&texture_creator, &ttf_context);
+    load_sounds(&mut resources);
+    if let Some(name) = &options.skin {
+        load_skin(&mut resources, name)?;
+    }
 
     let mut event_pump = sdl_context.event_pump()?;
 
-    let mut game = Game::new();
+    if options.versus {
+        return run_versus(
+            &options,
+            &mut canvas,
+            &mut resources,
+            &mut event_pump,
+            &music,
+        );
+    }
+    if let Some(netplay) = netplay {
+        return run_netplay(
+            &options,
+            netplay,
+            spectator,
+            &mut canvas,
+            &mut resources,
+            &mut event_pump,
+            &music,
+        );
+    }
+
+    // コマンドログと同じファイルでも上書きされる前に読み込む
+    let mut ghost = match &options.ghost {
+        Some(filename) => Some(Ghost::load(filename, "resources/data/stage1.dat")?),
+        None => None,
+    };
+    let mut game = new_game(&options)?;
+    let mut rewind = new_rewind(&options, &game);
+    let mut block_colors = new_block_colors(&options)?;
+    let mut effects = Effects::new();
 
     println!("Keys:");
-    println!("  Left, Right : Move player");
-    println!("  Up          : Scroll");
-    println!("  Space       : Shoot");
+    if options.coop {
+        println!("  Player 1    : A, D : Move  W : Scroll  S    : Shoot");
+        println!("  Player 2    : Left, Right : Move  Up : Scroll  Down : Shoot");
+    } else {
+        println!("  Left, Right : Move player");
+        println!("  Up          : Scroll");
+        println!("  Space       : Shoot");
+    }
+    if options.practice {
+        println!(
+            "  Backspace   : Hold to rewind (practice runs are not accepted by verify-replay)"
+        );
+    }
     println!("  Enter       : Restart when gameover");
 
     start_music(&music);
@@ -91,19 +491,26 @@ pub fn main() -> Result<(), String> {
     'running: loop {
         let started = SystemTime::now();
 
-        let mut command = Command::None;
         let mut is_keydown = false;
 
         let keyboard_state = event_pump.keyboard_state();
-        if keyboard_state.is_scancode_pressed(sdl2::keyboard::Scancode::Left) {
-            command = Command::Left;
-        } else if keyboard_state.is_scancode_pressed(sdl2::keyboard::Scancode::Right) {
-            command = Command::Right;
-        } else if keyboard_state.is_scancode_pressed(sdl2::keyboard::Scancode::Up) {
-            command = Command::Up;
-        } else if keyboard_state.is_scancode_pressed(sdl2::keyboard::Scancode::Space) {
-            command = Command::Shoot;
-        }
+        let mut commands = if options.coop {
+            vec![
+                keys_to_command(&keyboard_state, PLAYER1_KEYS),
+                keys_to_command(&keyboard_state, PLAYER2_KEYS),
+            ]
+        } else {
+            vec![keys_to_command(
+                &keyboard_state,
+                [
+                    Scancode::Left,
+                    Scancode::Right,
+                    Scancode::Up,
+                    Scancode::Space,
+                ],
+            )]
+        };
+        let is_rewinding = keyboard_state.is_scancode_pressed(Scancode::Backspace);
 
         for event in event_pump.poll_iter() {
             match event {
@@ -118,7 +525,12 @@ pub fn main() -> Result<(), String> {
                     is_keydown = true;
                     match code {
                         Keycode::Return => {
-                            game = Game::new();
+                            game = new_game(&options)?;
+                            rewind = new_rewind(&options, &game);
+                            effects = Effects::new();
+                            if let Some(ghost) = &mut ghost {
+                                ghost.reset();
+                            }
                             start_music(&music);
                         }
                         Keycode::F1 => {
@@ -130,6 +542,21 @@ pub fn main() -> Result<(), String> {
                             game.field.print_with_coord();
                             println!("{:?}", game);
                         }
+                        Keycode::F12 => {
+                            let filename = format!("screenshot-{}.png", game.frame);
+                            let mut view = GameView::new(&game);
+                            if let Some(ghost) = &ghost {
+                                view = view.with_ghost(ghost, &game);
+                            }
+                            save_screenshot(
+                                &mut canvas,
+                                &mut resources,
+                                &mut block_colors.clone(),
+                                &view,
+                                &filename,
+                            )?;
+                            println!("saved {}", filename);
+                        }
                         _ => {}
                     };
                 }
@@ -137,24 +564,521 @@ pub fn main() -> Result<(), String> {
             }
         }
 
-        if !game.is_debug || is_keydown {
-            game.update(command);
+        if let (Some(rewind), true) = (&mut rewind, is_rewinding) {
+            rewind.step_back(&mut game);
+        } else if !game.is_debug || is_keydown {
+            if let Some(rewind) = &mut rewind {
+                rewind.resume(&mut game);
+            }
+            if let Some(bot) = &mut bot {
+                bot.send_state(&game)?;
+                commands[0] = bot.receive_command()?;
+            }
+            game.update_players(&commands);
+            if let Some(ghost) = &mut ghost {
+                ghost.update();
+            }
+
+            let events = std::mem::take(&mut game.events);
+            if let Some(spectator) = &mut spectator {
+                spectator.publish(&game, &events);
+            }
+            effects.update(&events, &game.field, game.scroll_offset(), &block_colors);
+            if let Some(rewind) = &mut rewind {
+                rewind.push(&game);
+            }
+        }
+        let mut view = GameView::new(&game);
+        if let Some(ghost) = &ghost {
+            view = view.with_ghost(ghost, &game);
         }
-        render(&mut canvas, &game, &mut resources)?;
+        SdlRenderer {
+            canvas: &mut canvas,
+            colors: &mut block_colors,
+            effects: &mut effects,
+            resources: &mut resources,
+        }
+        .render(&view)?;
 
         play_sounds(&mut game, &resources);
 
-        let finished = SystemTime::now();
-        let elapsed = finished.duration_since(started).unwrap();
-        let frame_duration = Duration::new(0, 1_000_000_000u32 / model::FPS as u32);
-        if elapsed < frame_duration {
-            ::std::thread::sleep(frame_duration - elapsed)
+        wait_for_next_frame(started);
+    }
+
+    Ok(())
+}
+
+// 練習モードでは直近REWIND_SECONDS秒までさかのぼれる
+const REWIND_SECONDS: i32 = 20;
+
+fn new_rewind(options: &Options, game: &Game) -> Option<Rewind> {
+    if !options.practice {
+        return None;
+    }
+    let mut rewind = Rewind::new((FPS * REWIND_SECONDS) as usize);
+    rewind.push(game);
+    Some(rewind)
+}
+
+// 左右に2つのフィールドを並べて対戦する
+fn run_versus(
+    options: &Options,
+    canvas: &mut Canvas<Window>,
+    resources: &mut Resources,
+    event_pump: &mut sdl2::EventPump,
+    music: &mixer::Music,
+) -> Result<(), String> {
+    let mut versus = new_versus();
+    let mut block_colors = [new_block_colors(options)?, new_block_colors(options)?];
+    let mut effects = [Effects::new(), Effects::new()];
+
+    println!("Keys:");
+    println!("  Player 1    : A, D : Move  W : Scroll  S    : Shoot");
+    println!("  Player 2    : Left, Right : Move  Up : Scroll  Down : Shoot");
+    println!("  Enter       : Restart when finished");
+
+    start_music(music);
+
+    'running: loop {
+        let started = SystemTime::now();
+
+        let keyboard_state = event_pump.keyboard_state();
+        let commands = [
+            keys_to_command(&keyboard_state, PLAYER1_KEYS),
+            keys_to_command(&keyboard_state, PLAYER2_KEYS),
+        ];
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(code),
+                    ..
+                } => match code {
+                    Keycode::Escape => break 'running,
+                    Keycode::Return if versus.is_finished => {
+                        versus = new_versus();
+                        effects = [Effects::new(), Effects::new()];
+                        start_music(music);
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+
+        versus.update(commands);
+
+        canvas.set_draw_color(Color::RGB(0, 0, 32));
+        canvas.clear();
+        for (i, game) in versus.games.iter_mut().enumerate() {
+            let events = std::mem::take(&mut game.events);
+            effects[i].update(&events, &game.field, game.scroll_offset(), &block_colors[i]);
+            canvas.set_viewport(Rect::new(
+                SCREEN_WIDTH * i as i32,
+                0,
+                SCREEN_WIDTH as u32,
+                SCREEN_HEIGHT as u32,
+            ));
+            render_game(
+                canvas,
+                &GameView::new(game),
+                &mut block_colors[i],
+                &mut effects[i],
+                resources,
+            )?;
+
+            if versus.is_finished {
+                let text = match versus.winner {
+                    Some(winner) if winner == i => "WIN",
+                    Some(_) => "LOSE",
+                    None => "DRAW",
+                };
+                render_font(
+                    canvas,
+                    resources,
+                    "boxfont",
+                    text.to_string(),
+                    (SCREEN_WIDTH - INFO_WIDTH) / 2,
+                    255,
+                    Color::RGBA(255, 255, 128, 255),
+                    true,
+                );
+            }
+
+            play_sounds(game, resources);
+        }
+        canvas.set_viewport(None);
+        canvas.present();
+        resources.texts.end_frame();
+
+        wait_for_next_frame(started);
+    }
+
+    Ok(())
+}
+
+// ネットワーク越しに2人で協力プレイする。どちらのプレイヤーも矢印キーとSpaceで操作する
+fn run_netplay(
+    options: &Options,
+    mut netplay: Netplay,
+    mut spectator: Option<Spectator>,
+    canvas: &mut Canvas<Window>,
+    resources: &mut Resources,
+    event_pump: &mut sdl2::EventPump,
+    music: &mixer::Music,
+) -> Result<(), String> {
+    let mut game = netplay.new_game("resources/data/stage1.dat", create_recorder(options)?);
+    game.hash_interval = options.hash_interval;
+    let mut block_colors = new_block_colors(options)?;
+    let mut effects = Effects::new();
+
+    println!("You are player {}", netplay.local_player + 1);
+    println!("Keys:");
+    println!("  Left, Right : Move player");
+    println!("  Up          : Scroll");
+    println!("  Space       : Shoot");
+
+    start_music(music);
+
+    'running: loop {
+        let started = SystemTime::now();
+
+        let keyboard_state = event_pump.keyboard_state();
+        let command = keys_to_command(
+            &keyboard_state,
+            [
+                Scancode::Left,
+                Scancode::Right,
+                Scancode::Up,
+                Scancode::Space,
+            ],
+        );
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                _ => {}
+            }
+        }
+
+        netplay.update(&mut game, command)?;
+
+        let events = std::mem::take(&mut game.events);
+        if let Some(spectator) = &mut spectator {
+            spectator.publish(&game, &events);
         }
+        effects.update(&events, &game.field, game.scroll_offset(), &block_colors);
+        SdlRenderer {
+            canvas,
+            colors: &mut block_colors,
+            effects: &mut effects,
+            resources,
+        }
+        .render(&GameView::new(&game))?;
+
+        play_sounds(&mut game, resources);
+
+        wait_for_next_frame(started);
     }
 
     Ok(())
 }
 
+// 2人で遊ぶときのキー割り当て
+const PLAYER1_KEYS: [Scancode; 4] = [Scancode::A, Scancode::D, Scancode::W, Scancode::S];
+const PLAYER2_KEYS: [Scancode; 4] = [
+    Scancode::Left,
+    Scancode::Right,
+    Scancode::Up,
+    Scancode::Down,
+];
+
+// [左, 右, 上, 弾] のキーの押下状態をコマンドにする
+fn keys_to_command(keyboard_state: &sdl2::keyboard::KeyboardState, keys: [Scancode; 4]) -> Command {
+    if keyboard_state.is_scancode_pressed(keys[0]) {
+        Command::Left
+    } else if keyboard_state.is_scancode_pressed(keys[1]) {
+        Command::Right
+    } else if keyboard_state.is_scancode_pressed(keys[2]) {
+        Command::Up
+    } else if keyboard_state.is_scancode_pressed(keys[3]) {
+        Command::Shoot
+    } else {
+        Command::None
+    }
+}
+
+// リプレイを再生し、記録されたハッシュと食い違う最初のフレームを報告する
+fn check_replay_hashes(filename: &str) -> Result<(), String> {
+    let replay = Replay::load(filename)?;
+    if replay.hashes.is_empty() {
+        return Err(format!(
+            "{} has no hashes (record with --hash-interval)",
+            filename
+        ));
+    }
+    let game = replay.new_game("resources/data/stage1.dat")?;
+    let checked = replay.verify(game).map_err(|desync| desync.to_string())?;
+    println!(
+        "{}: {} frames, {} hashes matched",
+        filename,
+        replay.commands.len(),
+        checked
+    );
+    Ok(())
+}
+
+// リーダーボード用に、リプレイが記録どおりの結果になるかを確かめて署名付きの結果を出す
+fn sign_replay(filename: &str) -> Result<(), String> {
+    let key =
+        std::env::var(SIGNING_KEY_VAR).map_err(|_| format!("{} is not set", SIGNING_KEY_VAR))?;
+    let replay = Replay::load(filename)?;
+    let summary = leaderboard::verify_replay(&replay, "resources/data/stage1.dat")
+        .map_err(|e| format!("rejected {}: {}", filename, e))?;
+    println!("{}", summary.to_json(key.as_bytes()));
+    Ok(())
+}
+
+// リプレイを指定したフレームまで進め、その画面を画像に書き出す
+fn render_replay_frame(options: &Options) -> Result<(), String> {
+    let filename = options.replay.as_ref().unwrap();
+    let frame = options.frame.unwrap();
+    let output = match &options.output {
+        Some(output) => output.clone(),
+        None => format!("frame-{}.png", frame),
+    };
+
+    let replay = Replay::load(filename)?;
+    let mut game = replay.new_game("resources/data/stage1.dat")?;
+    replay.play_to(&mut game, frame);
+
+    let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
+    let mut canvas = offscreen_canvas()?;
+    let texture_creator = canvas.texture_creator();
+    let mut resources = load_resources(&texture_creator, &ttf_context);
+    if let Some(name) = &options.skin {
+        load_skin(&mut resources, name)?;
+    }
+    let mut block_colors = new_block_colors(options)?;
+    save_screenshot(
+        &mut canvas,
+        &mut resources,
+        &mut block_colors,
+        &GameView::new(&game),
+        &output,
+    )?;
+    println!("saved {}", output);
+    Ok(())
+}
+
+// リプレイを再生し直し、範囲内の各フレームをアニメーションGIFか連番のPNGに書き出す
+// 出力先が.gifで終わらなければディレクトリとみなし、frame-NNNNN.pngを並べる
+fn export_replay(options: &Options) -> Result<(), String> {
+    let filename = options.replay.as_ref().unwrap();
+    let output = match &options.output {
+        Some(output) => output.clone(),
+        None => "replay.gif".to_string(),
+    };
+
+    let replay = Replay::load(filename)?;
+    let from = options.from.unwrap_or(0);
+    let to = options.to.unwrap_or(replay.commands.len() as i32 - 1);
+    if to < from {
+        return Err(format!("no frames between {} and {}", from, to));
+    }
+    let mut game = replay.new_game("resources/data/stage1.dat")?;
+
+    let is_gif = output.ends_with(".gif");
+    if !is_gif {
+        fs::create_dir_all(&output).map_err(|e| format!("{}: {}", output, e))?;
+    }
+    let mut gif = None;
+
+    let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
+    let mut canvas = offscreen_canvas()?;
+    let texture_creator = canvas.texture_creator();
+    let mut resources = load_resources(&texture_creator, &ttf_context);
+    if let Some(name) = &options.skin {
+        load_skin(&mut resources, name)?;
+    }
+    let mut block_colors = new_block_colors(options)?;
+
+    for frame in from..=to {
+        replay.play_to(&mut game, frame);
+        let view = GameView::new(&game);
+        let image = capture(&mut canvas, &mut resources, &mut block_colors, &view)?;
+        let image = image.scale(options.scale);
+        if !is_gif {
+            image.save_png(&format!("{}/frame-{:05}.png", output, frame))?;
+            continue;
+        }
+        if gif.is_none() {
+            gif = Some(GifWriter::create(&output, image.width, image.height)?);
+        }
+        gif.as_mut().unwrap().add_frame(&image)?;
+    }
+    println!("saved frames {}-{} to {}", from, to, output);
+    Ok(())
+}
+
+// ウィンドウと同じ描画処理でオフスクリーンのサーフェスに描き、画像ファイルに保存する
+// 拡張子が.bmpならBMP、それ以外はPNG
+// 読み込み済みのリソースを使って描き、画面には出さずに画像に書き出す
+fn save_screenshot<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    resources: &mut Resources<T::Context>,
+    block_colors: &mut BlockColors,
+    view: &GameView,
+    filename: &str,
+) -> Result<(), String> {
+    let mut image = capture(canvas, resources, block_colors, view)?;
+
+    if filename.ends_with(".bmp") {
+        let surface = Surface::from_data(
+            &mut image.pixels,
+            image.width,
+            image.height,
+            image.width * 3,
+            PixelFormatEnum::RGB24,
+        )?;
+        surface.save_bmp(filename)
+    } else {
+        image.save_png(filename)
+    }
+}
+
+// ウィンドウと同じ大きさのオフスクリーンの描画先
+fn offscreen_canvas() -> Result<Canvas<Surface<'static>>, String> {
+    let surface = Surface::new(
+        SCREEN_WIDTH as u32,
+        SCREEN_HEIGHT as u32,
+        PixelFormatEnum::RGB24,
+    )?;
+    let mut canvas = surface.into_canvas()?;
+    canvas.set_blend_mode(BlendMode::Blend);
+    Ok(canvas)
+}
+
+fn capture<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    resources: &mut Resources<T::Context>,
+    block_colors: &mut BlockColors,
+    view: &GameView,
+) -> Result<RgbImage, String> {
+    // 画像に残すときは演出を描かない
+    SdlRenderer {
+        canvas: &mut *canvas,
+        colors: block_colors,
+        effects: &mut Effects::new(),
+        resources: &mut *resources,
+    }
+    .draw(view)?;
+    let pixels = canvas.read_pixels(None, PixelFormatEnum::RGB24)?;
+    resources.texts.end_frame();
+    RgbImage::new(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, pixels)
+}
+
+// ウィンドウを開かずにボットの操作だけでゲームを進める
+fn run_headless(
+    options: &Options,
+    mut bot: Option<BotServer>,
+    mut spectator: Option<Spectator>,
+) -> Result<(), String> {
+    let mut game = new_game(options)?;
+
+    while !game.is_over && !game.is_clear {
+        let started = SystemTime::now();
+
+        let mut command = Command::None;
+        if let Some(bot) = &mut bot {
+            bot.send_state(&game)?;
+            command = bot.receive_command()?;
+        }
+        game.update(command);
+        game.requested_sounds.clear();
+
+        let events = std::mem::take(&mut game.events);
+        if let Some(spectator) = &mut spectator {
+            spectator.publish(&game, &events);
+        }
+
+        if !options.lockstep {
+            wait_for_next_frame(started);
+        }
+    }
+
+    if let Some(bot) = &mut bot {
+        bot.send_state(&game)?;
+    }
+    println!("score = {}", game.score);
+
+    Ok(())
+}
+
+// SSH越しなどウィンドウを開けない環境で、端末に描画して遊ぶ
+// 押しっぱなしは取れないので、そのフレームに最後に押されたキーをコマンドにする
+fn run_terminal(options: &Options, mut spectator: Option<Spectator>) -> Result<(), String> {
+    let mut ghost = match &options.ghost {
+        Some(filename) => Some(Ghost::load(filename, "resources/data/stage1.dat")?),
+        None => None,
+    };
+    let mut game = new_game(options)?;
+
+    let mut input = TerminalInput::start()?;
+    let mut renderer = TerminalRenderer::new(std::io::stdout(), new_block_colors(options)?);
+
+    loop {
+        let started = SystemTime::now();
+
+        let mut command = Command::None;
+        for key in input.keys() {
+            match key {
+                TerminalKey::Command(c) => command = c,
+                TerminalKey::Restart => {
+                    game = new_game(options)?;
+                    if let Some(ghost) = &mut ghost {
+                        ghost.reset();
+                    }
+                }
+                TerminalKey::Quit => return Ok(()),
+            }
+        }
+
+        game.update(command);
+        game.requested_sounds.clear();
+        if let Some(ghost) = &mut ghost {
+            ghost.update();
+        }
+
+        let events = std::mem::take(&mut game.events);
+        if let Some(spectator) = &mut spectator {
+            spectator.publish(&game, &events);
+        }
+
+        let mut view = GameView::new(&game);
+        if let Some(ghost) = &ghost {
+            view = view.with_ghost(ghost, &game);
+        }
+        renderer.render(&view)?;
+
+        wait_for_next_frame(started);
+    }
+}
+
+fn wait_for_next_frame(started: SystemTime) {
+    let finished = SystemTime::now();
+    let elapsed = finished.duration_since(started).unwrap();
+    let frame_duration = Duration::new(0, 1_000_000_000u32 / model::FPS as u32);
+    if elapsed < frame_duration {
+        ::std::thread::sleep(frame_duration - elapsed)
+    }
+}
+
 fn init_mixer() {
     let chunk_size = 1_024;
     mixer::open_audio(
@@ -173,15 +1097,17 @@ fn start_music(music: &mixer::Music) {
     sdl2::mixer::Music::set_pos(MUSIC_START_POS_IN_SEC).unwrap_or(());
 }
 
-fn load_resources<'a>(
-    texture_creator: &'a TextureCreator<WindowContext>,
-    #[allow(unused_variables)] canvas: &mut Canvas<Window>,
+fn load_resources<'a, C>(
+    texture_creator: &'a TextureCreator<C>,
     ttf_context: &'a Sdl2TtfContext,
-) -> Resources<'a> {
+) -> Resources<'a, C> {
     let mut resources = Resources {
         images: HashMap::new(),
         chunks: HashMap::new(),
         fonts: HashMap::new(),
+        texts: FrameCache::new(),
+        texture_creator,
+        skin: None,
     };
 
     let entries = fs::read_dir("resources/image").unwrap();
@@ -201,18 +1127,6 @@ fn load_resources<'a>(
         }
     }
 
-    let entries = fs::read_dir("./resources/sound").unwrap();
-    for entry in entries {
-        let path = entry.unwrap().path();
-        let path_str = path.to_str().unwrap();
-        if path_str.ends_with(".wav") {
-            let chunk = mixer::Chunk::from_file(path_str)
-                .expect(&format!("cannot load sound: {}", path_str));
-            let basename = path.file_name().unwrap().to_str().unwrap();
-            resources.chunks.insert(basename.to_string(), chunk);
-        }
-    }
-
     load_font(
         &mut resources,
         &ttf_context,
@@ -232,8 +1146,50 @@ fn load_resources<'a>(
     resources
 }
 
-fn load_font<'a>(
-    resources: &mut Resources<'a>,
+// resources/skin/NAMEのスプライトシートを読み込む
+fn load_skin<C>(resources: &mut Resources<C>, name: &str) -> Result<(), String> {
+    let skin = Skin::load(name)?;
+    let mut texture = if skin.sheet.ends_with(".bmp") {
+        let surface = Surface::load_bmp(&skin.sheet)?;
+        resources
+            .texture_creator
+            .create_texture_from_surface(&surface)
+    } else {
+        let mut image = RgbaImage::load_png(&skin.sheet)?;
+        let surface = Surface::from_data(
+            &mut image.pixels,
+            image.width,
+            image.height,
+            image.width * 4,
+            PixelFormatEnum::RGBA32,
+        )?;
+        resources
+            .texture_creator
+            .create_texture_from_surface(&surface)
+    }
+    .map_err(|e| format!("{}: {}", skin.sheet, e))?;
+    texture.set_blend_mode(BlendMode::Blend);
+    resources.skin = Some(SkinSheet { skin, texture });
+    Ok(())
+}
+
+// 音はウィンドウを開いて遊ぶときだけ読み込む
+fn load_sounds<C>(resources: &mut Resources<C>) {
+    let entries = fs::read_dir("./resources/sound").unwrap();
+    for entry in entries {
+        let path = entry.unwrap().path();
+        let path_str = path.to_str().unwrap();
+        if path_str.ends_with(".wav") {
+            let chunk = mixer::Chunk::from_file(path_str)
+                .expect(&format!("cannot load sound: {}", path_str));
+            let basename = path.file_name().unwrap().to_str().unwrap();
+            resources.chunks.insert(basename.to_string(), chunk);
+        }
+    }
+}
+
+fn load_font<'a, C>(
+    resources: &mut Resources<'a, C>,
     ttf_context: &'a Sdl2TtfContext,
     path_str: &str,
     point_size: u16,
@@ -245,55 +1201,125 @@ fn load_font<'a>(
     resources.fonts.insert(key.to_string(), font);
 }
 
-fn render(
-    canvas: &mut Canvas<Window>,
-    game: &Game,
-    resources: &mut Resources,
-) -> Result<(), String> {
-    canvas.set_draw_color(Color::RGB(0, 0, 32));
-    canvas.clear();
+// SDLのウィンドウやオフスクリーンのサーフェスに描画する
+struct SdlRenderer<'c, 'r, T: RenderTarget> {
+    canvas: &'c mut Canvas<T>,
+    colors: &'c mut BlockColors,
+    effects: &'c mut Effects,
+    resources: &'c mut Resources<'r, T::Context>,
+}
 
-    let font = resources.fonts.get_mut("boxfont").unwrap();
+impl<T: RenderTarget> SdlRenderer<'_, '_, T> {
+    // 画面に出さずに描くだけ。presentする前ならread_pixelsで読み出せる
+    fn draw(&mut self, view: &GameView) -> Result<(), String> {
+        self.canvas.set_draw_color(Color::RGB(0, 0, 32));
+        self.canvas.clear();
+
+        render_game(self.canvas, view, self.colors, self.effects, self.resources)?;
+        if let Some(ghost) = &view.ghost {
+            render_ghost(self.canvas, ghost, scroll_offset_y(view), self.resources)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: RenderTarget> Renderer for SdlRenderer<'_, '_, T> {
+    fn render(&mut self, view: &GameView) -> Result<(), String> {
+        self.draw(view)?;
+
+        self.canvas.present();
+        self.resources.texts.end_frame();
+
+        Ok(())
+    }
+}
+
+// 1人分のフィールドと情報欄を描画する
+fn render_game<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    view: &GameView,
+    colors: &mut BlockColors,
+    effects: &mut Effects,
+    resources: &mut Resources<T::Context>,
+) -> Result<(), String> {
     let font_color = Color::RGB(0x6A, 0x5D, 0x1F);
     let font_color2 = Color::RGB(0x76, 0x6E, 0x5A);
 
+    // 揺れている間は情報欄以外をずらして描く
+    let viewport = canvas.viewport();
+    let (dx, dy) = effects.shake_offset();
+    canvas.set_viewport(Rect::new(
+        viewport.x() + dx,
+        viewport.y() + dy,
+        viewport.width(),
+        viewport.height(),
+    ));
+
     // render field
+    // スクロールの途中はフィールド全体を少し下げて、次に入ってくる行を上に見せる
+    colors.assign(view.field);
+    let offset_y = scroll_offset_y(view);
+    let cell_rect = |x: usize, y: i32| {
+        Rect::new(
+            x as i32 * CELL_SIZE,
+            y * CELL_SIZE + offset_y,
+            CELL_SIZE as u32,
+            CELL_SIZE as u32,
+        )
+    };
+    if let Some(row) = view.incoming_row.filter(|_| offset_y > 0) {
+        for x in 0..FIELD_W {
+            let ch = row[x];
+            if ch == EMPTY {
+                continue;
+            }
+            let below = view.field.cells[0][x];
+            let borders = Borders {
+                top: false,
+                right: x + 1 < FIELD_W && row[x + 1] != ch,
+                bottom: below != ch,
+                left: x > 0 && row[x - 1] != ch,
+            };
+            // 下の行と同じブロックなら同じ色にする
+            let (color, glyph) = if below == ch {
+                (colors.color(x, 0).unwrap(), colors.glyph(x, 0))
+            } else {
+                (colors.color_of(ch), colors.glyph_of(ch))
+            };
+            render_block(canvas, resources, cell_rect(x, -1), color, borders, glyph)?;
+        }
+    }
     for y in 0..FIELD_H {
         for x in 0..FIELD_W {
-            let ch = game.field.cells[y][x];
+            let ch = view.field.cells[y][x];
+            let rect = cell_rect(x, y as i32);
             if ch == ERASING {
-                let color = Color::RGB(255, 255, 255);
-                canvas.set_draw_color(color);
-                canvas.fill_rect(Rect::new(
-                    x as i32 * CELL_SIZE,
-                    y as i32 * CELL_SIZE,
-                    CELL_SIZE as u32 - 1,
-                    CELL_SIZE as u32 - 1,
-                ))?;
+                if let Some(sheet) = &mut resources.skin {
+                    let tile = sheet.skin.erase_tile(view.frame);
+                    sheet.draw(canvas, tile, (255, 255, 255), rect)?;
+                } else {
+                    let color = Color::RGB(255, 255, 255);
+                    canvas.set_draw_color(color);
+                    canvas.fill_rect(Rect::new(
+                        rect.x(),
+                        rect.y(),
+                        CELL_SIZE as u32 - 1,
+                        CELL_SIZE as u32 - 1,
+                    ))?;
+                }
             } else if ch != EMPTY {
-                let color_index = (ch as i32) % 6;
-                let color = match color_index {
-                    1 => Color::RGB(255, 128, 128),
-                    2 => Color::RGB(128, 255, 128),
-                    3 => Color::RGB(128, 128, 255),
-                    4 => Color::RGB(255, 255, 128),
-                    5 => Color::RGB(128, 255, 255),
-                    0 => Color::RGB(255, 128, 255),
-                    _ => panic!("Invalid n: {}", ch),
-                };
-                canvas.set_draw_color(color);
-                canvas.fill_rect(Rect::new(
-                    x as i32 * CELL_SIZE,
-                    y as i32 * CELL_SIZE,
-                    CELL_SIZE as u32,
-                    CELL_SIZE as u32,
-                ))?;
+                let mut borders = view.field.borders(x, y);
+                if let Some(row) = view.incoming_row.filter(|_| y == 0 && offset_y > 0) {
+                    borders.top = row[x] != ch;
+                }
+                let color = colors.color(x, y).unwrap();
+                render_block(canvas, resources, rect, color, borders, colors.glyph(x, y))?;
             }
         }
     }
 
     // render sight
-    if let Some(sight_pos) = game.get_sight_pos() {
+    for sight_pos in view.sights.iter().flatten() {
         let image = resources.images.get("sight.bmp").unwrap();
         canvas
             .copy(
@@ -301,7 +1327,7 @@ fn render(
                 Rect::new(0, 0, image.w, image.h),
                 Rect::new(
                     sight_pos.x as i32 * CELL_SIZE,
-                    sight_pos.y as i32 * CELL_SIZE,
+                    sight_pos.y as i32 * CELL_SIZE + offset_y,
                     image.w as u32,
                     image.h as u32,
                 ),
@@ -309,42 +1335,48 @@ fn render(
             .unwrap();
     }
 
-    // render player
-    canvas.set_draw_color(Color::RGB(192, 192, 192));
-    let offset_x;
-    if game.move_wait > 0 {
-        offset_x = ((if game.move_dir == Direction::Left {
-            -1.0
+    // render players
+    for (i, player) in view.players.iter().enumerate() {
+        let rect = Rect::new(
+            player.x as i32 * CELL_SIZE + player_offset_x(player),
+            SCREEN_HEIGHT - CELL_SIZE,
+            CELL_SIZE as u32,
+            CELL_SIZE as u32,
+        );
+        if let Some(sheet) = &mut resources.skin {
+            let tile = sheet.skin.cannon_tile();
+            sheet.draw(canvas, tile, player_color(i), rect)?;
         } else {
-            1.0
-        }) * ((MOVE_WAIT - game.move_wait) as f32 / MOVE_WAIT as f32)
-            * CELL_SIZE as f32) as i32;
-    } else {
-        offset_x = 0;
+            canvas.set_draw_color(Color::from(player_color(i)));
+            canvas.fill_rect(rect)?;
+        }
     }
-    canvas.fill_rect(Rect::new(
-        game.player_x as i32 * CELL_SIZE + offset_x,
-        SCREEN_HEIGHT - CELL_SIZE,
-        CELL_SIZE as u32,
-        CELL_SIZE as u32,
-    ))?;
 
     // render bullets
-    for bullet in &game.bullets {
-        canvas.set_draw_color(Color::RGB(128, 128, 128));
-        canvas.fill_rect(Rect::new(
+    for bullet in view.bullets {
+        let (r, g, b) = player_color(bullet.owner);
+        let color = (r / 3 * 2, g / 3 * 2, b / 3 * 2);
+        let rect = Rect::new(
             bullet.pos.x as i32 * CELL_SIZE,
-            bullet.pos.y as i32 * CELL_SIZE + bullet.offset_y,
+            bullet.pos.y as i32 * CELL_SIZE + bullet.offset_y + offset_y,
             CELL_SIZE as u32,
             CELL_SIZE as u32,
-        ))?;
+        );
+        if let Some(sheet) = &mut resources.skin {
+            let tile = sheet.skin.bullet_tile();
+            sheet.draw(canvas, tile, color, rect)?;
+        } else {
+            canvas.set_draw_color(Color::from(color));
+            canvas.fill_rect(rect)?;
+        }
     }
 
     // render erased texts
-    for text in &game.erased_texts {
+    for text in view.erased_texts {
         render_font(
             canvas,
-            font,
+            resources,
+            "boxfont",
             text.text.clone(),
             text.x,
             text.y,
@@ -354,6 +1386,31 @@ fn render(
         );
     }
 
+    // render particles
+    for particle in &effects.particles {
+        let (r, g, b) = particle.color;
+        canvas.set_draw_color(Color::RGBA(r, g, b, (particle.life * 10).min(255) as u8));
+        canvas.fill_rect(Rect::new(
+            particle.x as i32 - 2,
+            particle.y as i32 - 2,
+            4,
+            4,
+        ))?;
+    }
+
+    // render flash
+    if effects.flash > 0 {
+        let alpha = effects.flash * 160 / FLASH_FRAMES;
+        canvas.set_draw_color(Color::RGBA(255, 255, 255, alpha as u8));
+        canvas.fill_rect(Rect::new(
+            0,
+            0,
+            (SCREEN_WIDTH - INFO_WIDTH) as u32,
+            SCREEN_HEIGHT as u32,
+        ))?;
+    }
+    canvas.set_viewport(viewport);
+
     // render info
     canvas.set_draw_color(Color::RGB(0, 0, 0));
     canvas.fill_rect(Rect::new(
@@ -364,8 +1421,9 @@ fn render(
     ))?;
     render_font(
         canvas,
-        font,
-        format!("{:3} pct", game.get_progress()).to_string(),
+        resources,
+        "boxfont",
+        format!("{:3} pct", view.progress).to_string(),
         SCREEN_WIDTH - INFO_WIDTH + 40,
         210,
         font_color,
@@ -373,23 +1431,39 @@ fn render(
     );
     render_font(
         canvas,
-        font,
-        format!("  {:05}", game.score).to_string(),
+        resources,
+        "boxfont",
+        format!("  {:05}", view.score).to_string(),
         SCREEN_WIDTH - INFO_WIDTH + 40,
         260,
         font_color2,
         false,
     );
+    if view.players.len() > 1 {
+        for (i, player) in view.players.iter().enumerate() {
+            render_font(
+                canvas,
+                resources,
+                "boxfont",
+                format!("{}P {:05}", i + 1, player.score).to_string(),
+                SCREEN_WIDTH - INFO_WIDTH + 40,
+                310 + i as i32 * 40,
+                Color::from(player_color(i)),
+                false,
+            );
+        }
+    }
 
-    if game.is_over {
+    if view.is_over {
         canvas.set_draw_color(Color::RGBA(255, 0, 0, 128));
         canvas.fill_rect(Rect::new(0, 0, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32))?;
     }
 
-    if game.is_clear {
+    if view.is_clear {
         render_font(
             canvas,
-            font,
+            resources,
+            "boxfont",
             "CONGRATULATIONS!".to_string(),
             (SCREEN_WIDTH - INFO_WIDTH) / 2,
             205,
@@ -398,12 +1472,12 @@ fn render(
         );
     }
 
-    if game.is_debug {
-        let font_xs = resources.fonts.get_mut("boxfont_xs").unwrap();
+    if view.is_debug {
         render_font(
             canvas,
-            font_xs,
-            format!("{}", game.frame).to_string(),
+            resources,
+            "boxfont_xs",
+            format!("{}", view.frame).to_string(),
             0,
             0,
             Color::RGBA(255, 255, 255, 255),
@@ -411,37 +1485,173 @@ fn render(
         );
     }
 
-    canvas.present();
+    Ok(())
+}
+
+// ゴーストの砲台と弾を半透明で重ね、スコアの差を情報欄に出す
+// 弾は今のゲームのフィールドに重ねるので、フィールドと同じだけ下げる
+fn render_ghost<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    ghost: &GhostView,
+    offset_y: i32,
+    resources: &mut Resources<T::Context>,
+) -> Result<(), String> {
+    let alpha = 96;
+
+    for player in ghost.players {
+        canvas.set_draw_color(Color::RGBA(255, 255, 255, alpha));
+        canvas.fill_rect(Rect::new(
+            player.x as i32 * CELL_SIZE + player_offset_x(player),
+            SCREEN_HEIGHT - CELL_SIZE,
+            CELL_SIZE as u32,
+            CELL_SIZE as u32,
+        ))?;
+    }
+
+    for bullet in ghost.bullets {
+        canvas.set_draw_color(Color::RGBA(255, 255, 255, alpha));
+        canvas.fill_rect(Rect::new(
+            bullet.pos.x as i32 * CELL_SIZE,
+            bullet.pos.y as i32 * CELL_SIZE + bullet.offset_y + offset_y,
+            CELL_SIZE as u32,
+            CELL_SIZE as u32,
+        ))?;
+    }
+
+    let color = if ghost.score_delta >= 0 {
+        Color::RGBA(128, 255, 128, 160)
+    } else {
+        Color::RGBA(255, 128, 128, 160)
+    };
+    render_font(
+        canvas,
+        resources,
+        "boxfont",
+        format!("{:+06}", ghost.score_delta),
+        SCREEN_WIDTH - INFO_WIDTH + 40,
+        400,
+        color,
+        false,
+    );
 
     Ok(())
 }
 
-fn render_font(
-    canvas: &mut Canvas<Window>,
-    font: &sdl2::ttf::Font,
+// 1マス分のブロック。スキンがあればスキンのタイルで描く
+fn render_block<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    resources: &mut Resources<T::Context>,
+    rect: Rect,
+    (r, g, b): (u8, u8, u8),
+    borders: Borders,
+    glyph: Option<char>,
+) -> Result<(), String> {
+    let dark = Color::RGB(r / 2, g / 2, b / 2);
+    if let Some(sheet) = &mut resources.skin {
+        let tile = sheet.skin.block_tile(borders);
+        sheet.draw(canvas, tile, (r, g, b), rect)?;
+    } else {
+        canvas.set_draw_color(Color::RGB(r, g, b));
+        canvas.fill_rect(rect)?;
+
+        // 別のブロックと接する辺に暗い線を引く
+        canvas.set_draw_color(dark);
+        for border in border_rects(rect, borders) {
+            canvas.fill_rect(border)?;
+        }
+    }
+    if let Some(glyph) = glyph {
+        render_font(
+            canvas,
+            resources,
+            "boxfont_xs",
+            glyph.to_string(),
+            rect.x() + CELL_SIZE / 2,
+            rect.y() + (CELL_SIZE - 14) / 2,
+            dark,
+            true,
+        );
+    }
+    Ok(())
+}
+
+// スクロールの途中でフィールドを下げて描く量（ピクセル）
+fn scroll_offset_y(view: &GameView) -> i32 {
+    (view.scroll_offset * CELL_SIZE as f32) as i32
+}
+
+// ブロックの境目の線。マスの内側に描く
+fn border_rects(cell: Rect, borders: Borders) -> Vec<Rect> {
+    const WIDTH: i32 = 2;
+    let left = cell.x();
+    let top = cell.y();
+    let mut rects = Vec::new();
+    if borders.top {
+        rects.push(Rect::new(left, top, CELL_SIZE as u32, WIDTH as u32));
+    }
+    if borders.bottom {
+        rects.push(Rect::new(
+            left,
+            top + CELL_SIZE - WIDTH,
+            CELL_SIZE as u32,
+            WIDTH as u32,
+        ));
+    }
+    if borders.left {
+        rects.push(Rect::new(left, top, WIDTH as u32, CELL_SIZE as u32));
+    }
+    if borders.right {
+        rects.push(Rect::new(
+            left + CELL_SIZE - WIDTH,
+            top,
+            WIDTH as u32,
+            CELL_SIZE as u32,
+        ));
+    }
+    rects
+}
+
+fn player_offset_x(player: &Player) -> i32 {
+    (player_offset(player) * CELL_SIZE as f32) as i32
+}
+
+// 同じフォント・文字列・色のテクスチャは前のフレームのものを使い回す
+#[allow(clippy::too_many_arguments)]
+fn render_font<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    resources: &mut Resources<T::Context>,
+    font: &'static str,
     text: String,
     x: i32,
     y: i32,
     color: Color,
     center: bool,
 ) {
-    let texture_creator = canvas.texture_creator();
-
-    let surface = font.render(&text).blended(color).unwrap();
-    let texture = texture_creator
-        .create_texture_from_surface(&surface)
+    let Resources {
+        fonts,
+        texts,
+        texture_creator,
+        ..
+    } = resources;
+    let texture = texts
+        .get_or_try_insert_with((font, text, color), |(font, text, color)| {
+            let surface = fonts[*font]
+                .render(text)
+                .blended(*color)
+                .map_err(|e| e.to_string())?;
+            texture_creator
+                .create_texture_from_surface(&surface)
+                .map_err(|e| e.to_string())
+        })
         .unwrap();
+    let query = texture.query();
     let x: i32 = if center {
-        x - texture.query().width as i32 / 2
+        x - query.width as i32 / 2
     } else {
         x
     };
     canvas
-        .copy(
-            &texture,
-            None,
-            Rect::new(x, y, texture.query().width, texture.query().height),
-        )
+        .copy(texture, None, Rect::new(x, y, query.width, query.height))
         .unwrap();
 }
 
@@ -1,9 +1,15 @@
 use rand::prelude::*;
 use std::collections::HashSet;
-use std::io::Write;
-use std::{fs::File, time};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time;
 
+use crate::bitboard::*;
+use crate::erase::*;
 use crate::field::*;
+use crate::hash::*;
+use crate::recorder::*;
+use crate::replay::*;
 
 pub const FPS: i32 = 30;
 pub const MOVE_WAIT: i32 = 3;
@@ -13,6 +19,7 @@ pub const BULLET_COUNT_MAX: i32 = 4;
 pub const BULLET_SPEED: i32 = 30;
 pub const CELL_SIZE: i32 = 30;
 pub const ERASING: Cell = '*';
+pub const GARBAGE: [Cell; 2] = ['X', 'Y']; // 対戦相手から送られた行。交互に使い、上下の行がつながらないようにする
 pub const ERASE_WAIT: i32 = 1;
 pub const ERASED_TEXT_VANISH_WAIT: i32 = 30;
 
@@ -38,7 +45,7 @@ impl Command {
     }
 }
 
-#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct Point {
     pub x: usize,
     pub y: usize,
@@ -50,7 +57,7 @@ impl Point {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum Direction {
     Left,
     Right,
@@ -58,24 +65,58 @@ pub enum Direction {
     Down,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Hash)]
+pub struct Player {
+    pub x: usize,
+    pub move_dir: Direction, // 移動の方向
+    pub move_wait: i32,
+    pub shoot_wait: i32,
+    pub score: i32, // この砲台で消した矩形の得点
+}
+
+impl Player {
+    pub fn new(x: usize) -> Player {
+        Player {
+            x,
+            move_dir: Direction::Left,
+            move_wait: 0,
+            shoot_wait: 0,
+            score: 0,
+        }
+    }
+
+    // 移動中なら移動先、そうでなければ現在の位置
+    pub fn destination_x(&self) -> usize {
+        if self.move_wait == 0 {
+            self.x
+        } else if self.move_dir == Direction::Left {
+            self.x - 1
+        } else {
+            self.x + 1
+        }
+    }
+}
+
+#[derive(Debug, Clone, Hash)]
 pub struct Bullet {
     pub pos: Point,
     pub offset_y: i32,
     pub exist: bool,
+    pub owner: usize, // 撃ったプレイヤー
 }
 
 impl Bullet {
-    pub fn new(x: usize) -> Bullet {
+    pub fn new(x: usize, owner: usize) -> Bullet {
         Bullet {
             pos: Point::new(x, FIELD_H - 2),
             offset_y: 0,
             exist: true,
+            owner,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Hash)]
 pub struct ErasingEffect {
     pub erased_block_count: i32,
     pub text: String,
@@ -84,9 +125,10 @@ pub struct ErasingEffect {
     pub erase_wait: i32,
     pub dir: Direction,
     pub rectangle: Rectangle,
+    pub owner: usize, // 得点を受け取るプレイヤー
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Hash)]
 pub struct ErasedText {
     pub text: String,
     pub x: i32,
@@ -95,69 +137,95 @@ pub struct ErasedText {
     pub vanish_wait: i32,
 }
 
-#[derive(Debug)]
+// フロントエンドや外部ツールに知らせる出来事
+// requested_soundsと同様に、受け取った側が取り出して空にする
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum GameEvent {
+    // 矩形の消去が始まった
+    EraseStarted {
+        rectangle: Rectangle,
+        block_count: i32,
+        player: usize,
+        blocks: Vec<(Point, Cell)>, // 消えるマスと、消える前の文字
+    },
+    // 矩形が消えきって得点が入った
+    Erased {
+        rectangle: Rectangle,
+        block_count: i32,
+        score: i32,
+        player: usize,
+    },
+}
+
+#[derive(Debug, Clone)]
 pub struct Game {
     pub rng: StdRng,
     pub is_over: bool,
     pub is_clear: bool,
     pub is_debug: bool,
     pub requested_sounds: Vec<&'static str>,
+    pub events: Vec<GameEvent>,
     pub frame: i32,
     pub field: Field,
-    pub stage: Vec<String>,
-    pub next_row: usize, // 次にstageからfieldにコピーする行のインデックス
-    pub player_x: usize,
-    pub move_dir: Direction, // プレイヤーの移動の方向
-    pub move_wait: i32,
-    pub shoot_wait: i32,
+    pub stage: Arc<[String]>, // ゲーム中は変わらないので、複製したGameと共有する
+    pub next_row: usize,      // 次にstageからfieldにコピーする行のインデックス
+    pub players: Vec<Player>,
     pub scroll_wait: i32,
+    pub paused_scroll_wait: Option<i32>, // 消去が始まったときのscroll_wait。描画はここで止める
+    pub garbage_rows: i32,               // 対戦相手から送られた、これから上から押し込む行数
     pub bullets: Vec<Bullet>,
     pub erasing_effects: Vec<ErasingEffect>,
+    pub erase_style: EraseStyle,
     pub erased_texts: Vec<ErasedText>,
-    pub score: i32,
-    pub commands: Vec<Command>, // リプレイデータから読み込んだコマンド
-    pub command_log: File,      // コマンドログ
+    pub score: i32,                  // 全プレイヤーの合計
+    pub commands: Vec<Vec<Command>>, // リプレイデータから読み込んだコマンド
+    pub recorder: Box<dyn Recorder>, // コマンドログの記録先
+    pub hash_interval: i32, // このフレーム数ごとに状態のハッシュを記録する。0なら記録しない
 }
 
 impl Game {
-    pub fn new() -> Self {
+    pub fn new(recorder: Box<dyn Recorder>) -> Self {
         let now = time::SystemTime::now();
         let timestamp = now
             .duration_since(time::UNIX_EPOCH)
             .expect("SystemTime before UNIX EPOCH!")
             .as_secs();
-        let rng = StdRng::seed_from_u64(timestamp);
         println!("random seed = {}", timestamp);
-        // let rng = StdRng::seed_from_u64(0);
 
-        let mut game = Game {
-            rng: rng,
+        let mut game = Game::with_seed(timestamp, recorder);
+        game.load_stage("resources/data/stage1.dat");
+        game.load_replay();
+
+        game
+    }
+
+    // ステージもリプレイも読み込まない、空のフィールドのGameを作る
+    pub fn with_seed(seed: u64, mut recorder: Box<dyn Recorder>) -> Self {
+        recorder.record_seed(seed);
+        Game {
+            rng: StdRng::seed_from_u64(seed),
             is_over: false,
             is_clear: false,
             is_debug: false,
             requested_sounds: Vec::new(),
+            events: Vec::new(),
             frame: -1,
             field: Field::new(),
-            stage: Vec::new(),
+            stage: Arc::new([]),
             next_row: 0,
-            player_x: FIELD_W / 2,
-            // player_offset: 0,
-            move_dir: Direction::Left,
-            move_wait: 0,
-            shoot_wait: 0,
+            players: vec![Player::new(FIELD_W / 2)],
             scroll_wait: SCROLL_WAIT,
+            paused_scroll_wait: None,
+            garbage_rows: 0,
             bullets: Vec::new(),
             erasing_effects: Vec::new(),
+            erase_style: EraseStyle::default(),
             erased_texts: Vec::new(),
             score: 0,
             commands: Vec::new(),
-            command_log: File::create("command.log").unwrap(),
-        };
-
-        game.load_stage("resources/data/stage1.dat");
-        game.load_replay();
-
-        game
+            recorder,
+            hash_interval: 0,
+        }
     }
 
     pub fn load_replay(&mut self) {
@@ -171,32 +239,102 @@ impl Game {
     }
 
     pub fn load_stage(&mut self, filename: &str) {
-        let Ok(content) = std::fs::read_to_string(filename) else {
-            panic!("Cannot load: {}", filename);
-        };
+        if let Err(e) = self.try_load_stage(filename) {
+            panic!("Cannot load: {}", e);
+        }
+    }
 
-        for (_, line) in content.lines().enumerate() {
-            let row = ((line.to_string() + &" ".repeat(FIELD_W as usize))[0..FIELD_W]).to_string();
+    // 読めなければパニックせずにErrを返す
+    pub fn try_load_stage(&mut self, filename: &str) -> Result<(), String> {
+        let content =
+            std::fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        self.set_stage(&content)
+            .map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    // ステージデータを文字列から読み込む。ファイルを読まないのでテストでも使える
+    pub fn set_stage(&mut self, content: &str) -> Result<(), String> {
+        let mut erase_style = None;
+        let mut stage = Vec::new();
+        for line in content.lines() {
+            // '#'で始まる行はステージの設定。"#erase wipe"で消去のアニメーションを選ぶ
+            if let Some(setting) = line.strip_prefix('#') {
+                match setting.split_whitespace().collect::<Vec<_>>().as_slice() {
+                    ["erase", name] => erase_style = Some(EraseStyle::from_name(name)?),
+                    _ => return Err(format!("unknown setting: {}", line)),
+                }
+                continue;
+            }
+            let row = ((line.to_string() + &" ".repeat(FIELD_W))[0..FIELD_W]).to_string();
             assert!(row.len() == FIELD_W);
-            self.stage.push(row);
+            stage.push(row);
+        }
+        if stage.is_empty() {
+            return Err("stage has no rows".to_string());
         }
 
+        self.stage = stage.into();
         self.next_row = self.stage.len() - 1;
+        let hash = self.stage_hash();
+        self.recorder.record_stage(hash);
+        if let Some(style) = erase_style {
+            self.set_erase_style(style);
+        }
+        Ok(())
+    }
+
+    // 消去のアニメーションを変える。リプレイで同じ消え方になるよう記録する
+    pub fn set_erase_style(&mut self, style: EraseStyle) {
+        self.erase_style = style;
+        self.recorder.record_erase_style(style);
+    }
+
+    // リプレイが同じステージで記録されたかを確かめるためのハッシュ
+    pub fn stage_hash(&self) -> u64 {
+        let mut hasher = StateHasher::new();
+        self.stage.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // 協力プレイの人数を設定し、砲台を等間隔に並べ直す
+    pub fn set_player_count(&mut self, count: usize) {
+        self.players = (0..count)
+            .map(|i| Player::new(FIELD_W * (i + 1) / (count + 1)))
+            .collect();
+    }
+
+    pub fn update(&mut self, command: Command) {
+        self.update_players(&[command]);
+    }
+
+    // プレイヤーごとのコマンドで1フレーム進める。足りない分はCommand::None
+    pub fn update_players(&mut self, commands: &[Command]) {
+        let was_finished = self.is_over || self.is_clear;
+        self.step(commands);
+
+        // 最後のフレームも確かめられるように、終わったフレームのハッシュは間隔によらず記録する
+        let is_finished = !was_finished && (self.is_over || self.is_clear);
+        if self.hash_interval > 0 && (self.frame % self.hash_interval == 0 || is_finished) {
+            let hash = self.state_hash();
+            self.recorder.record_hash(self.frame, hash);
+        }
     }
 
-    pub fn update(&mut self, mut command: Command) {
+    fn step(&mut self, commands: &[Command]) {
         self.frame += 1;
 
-        if self.commands.len() > 0 {
-            command = self.commands[self.frame as usize];
+        let mut commands = commands.to_vec();
+        if let Some(replay) = self.commands.get(self.frame as usize) {
+            commands = replay.clone();
         }
-        self.write_command_log(command);
+        commands.resize(self.players.len(), Command::None);
+        self.write_command_log(&commands);
 
         if self.is_over || self.is_clear {
             return;
         }
 
-        if command == Command::Up {
+        if commands.contains(&Command::Up) {
             if self.scroll_wait > 5 {
                 self.scroll_wait = 5;
             }
@@ -208,25 +346,37 @@ impl Game {
 
         if self.scroll_wait > 0 {
             self.scroll_wait -= 1;
-            if self.scroll_wait == 0 {
-                self.scroll();
-                self.scroll_wait = SCROLL_WAIT;
-            }
+        }
+        // 消去中に待ちが尽きたら0のまま保留し、消え終わってからスクロールする
+        // 待ちを戻してしまうと、描画でフィールドを下げる量が実際のスクロールなしに巻き戻る
+        if self.scroll_wait == 0 && (self.erasing_effects.is_empty() || self.next_row == 0) {
+            self.scroll();
+            self.scroll_wait = SCROLL_WAIT;
+        }
+
+        // 送られた行は1フレームに1行ずつ押し込む。ステージは進めない
+        if self.garbage_rows > 0 && self.erasing_effects.is_empty() {
+            self.push_garbage_row();
+            self.garbage_rows -= 1;
         }
 
         self.update_bullets();
         self.update_erasing_effects();
         self.update_erased_texts();
 
-        self.move_player();
-        if self.shoot_wait > 0 {
-            self.shoot_wait -= 1;
+        for i in 0..self.players.len() {
+            self.move_player(i);
+            if self.players[i].shoot_wait > 0 {
+                self.players[i].shoot_wait -= 1;
+            }
         }
 
-        match command {
-            Command::Shoot => self.shoot(),
-            Command::Left | Command::Right => self.start_move_player(command),
-            _ => {}
+        for (i, command) in commands.iter().enumerate() {
+            match command {
+                Command::Shoot => self.shoot(i),
+                Command::Left | Command::Right => self.start_move_player(i, *command),
+                _ => {}
+            }
         }
 
         self.check_gameover();
@@ -234,24 +384,21 @@ impl Game {
         self.bullets.retain(|x| x.exist);
         self.erasing_effects.retain(|x| x.exist);
         self.erased_texts.retain(|x| x.exist);
+
+        // 消去中はスクロールしないので、描画でフィールドを下げる量もその時点で止める
+        if self.erasing_effects.is_empty() {
+            self.paused_scroll_wait = None;
+        } else if self.paused_scroll_wait.is_none() {
+            self.paused_scroll_wait = Some(self.scroll_wait);
+        }
     }
 
-    pub fn write_command_log(&mut self, command: Command) {
-        self.command_log
-            .write_all(format!("{:?}\n", command).as_bytes())
-            .ok();
-        self.command_log.flush().ok();
+    pub fn write_command_log(&mut self, commands: &[Command]) {
+        self.recorder.record(commands);
     }
 
     pub fn is_field_empty(&self) -> bool {
-        for y in 0..FIELD_H {
-            for x in 0..FIELD_W {
-                if self.field.cells[y][x] != EMPTY {
-                    return false;
-                }
-            }
-        }
-        return true;
+        self.field.cells.iter().all(|row| *row == [EMPTY; FIELD_W])
     }
 
     pub fn scroll(&mut self) {
@@ -266,7 +413,29 @@ impl Game {
             return;
         }
 
-        // 1つ上の行をコピー
+        self.shift_field_down();
+
+        // ステージデータから1行読み込んでフィールドの一番上にセット
+        for x in 0..FIELD_W {
+            self.field.cells[0][x] = self.stage[self.next_row].chars().nth(x).unwrap();
+        }
+        self.next_row -= 1;
+    }
+
+    // 対戦相手から送られた行をフィールドの一番上に押し込む
+    // ステージは進まないので、送られるほどクリアから遠ざかり、ゲームオーバーに近づく
+    pub fn push_garbage_row(&mut self) {
+        self.shift_field_down();
+        let ch = if self.field.cells[1][0] == GARBAGE[0] {
+            GARBAGE[1]
+        } else {
+            GARBAGE[0]
+        };
+        self.field.cells[0] = [ch; FIELD_W];
+    }
+
+    // 1つ上の行をコピー
+    fn shift_field_down(&mut self) {
         for y in (1..=(FIELD_H - 1)).rev() {
             for x in 0..FIELD_W {
                 if self.field.cells[y][x] != ERASING && self.field.cells[y - 1][x] != ERASING {
@@ -274,29 +443,47 @@ impl Game {
                 }
             }
         }
+    }
 
-        // ステージデータから1行読み込んでフィールドの一番上にセット
-        for x in 0..FIELD_W {
-            self.field.cells[0][x] = self.stage[self.next_row].chars().nth(x).unwrap();
+    // 次のスクロールまでの進み具合（0.0以上1.0未満）。描画でフィールドを少しずつ下げるのに使う
+    // フィールドが実際に動くのはscrollのときだけなので、当たり判定には関わらない
+    // 消去中は消去が始まったときの位置で止め、最後の行まで来た後と終了後は0.0
+    pub fn scroll_offset(&self) -> f32 {
+        if self.is_over || self.is_clear || self.next_row == 0 {
+            return 0.0;
         }
-        self.next_row -= 1;
+        let scroll_wait = self.paused_scroll_wait.unwrap_or(self.scroll_wait);
+        (SCROLL_WAIT - scroll_wait.min(SCROLL_WAIT)) as f32 / SCROLL_WAIT as f32
+    }
+
+    // 次のスクロールでフィールドの一番上に入る行
+    pub fn incoming_row(&self) -> Option<[Cell; FIELD_W]> {
+        if self.next_row == 0 {
+            return None;
+        }
+        let mut row = [EMPTY; FIELD_W];
+        for (x, ch) in self.stage[self.next_row].chars().take(FIELD_W).enumerate() {
+            row[x] = ch;
+        }
+        Some(row)
     }
 
     // 移動中のアニメーション処理
-    pub fn move_player(&mut self) {
-        if self.move_wait > 0 {
-            self.move_wait -= 1;
-            if self.move_dir == Direction::Left {
+    pub fn move_player(&mut self, i: usize) {
+        let player = &mut self.players[i];
+        if player.move_wait > 0 {
+            player.move_wait -= 1;
+            if player.move_dir == Direction::Left {
                 // self.player_offset -= self.move_wait / MOVE_WAIT;
                 // if self.player_offset == 0 {
-                if self.move_wait == 0 {
-                    self.player_x -= 1;
+                if player.move_wait == 0 {
+                    player.x -= 1;
                 }
-            } else if self.move_dir == Direction::Right {
+            } else if player.move_dir == Direction::Right {
                 // self.player_offset += self.move_wait / MOVE_WAIT;
                 // if self.player_offset == 0 {
-                if self.move_wait == 0 {
-                    self.player_x += 1;
+                if player.move_wait == 0 {
+                    player.x += 1;
                 }
             }
         }
@@ -332,7 +519,7 @@ impl Game {
                 self.field.cells[self.bullets[i].pos.y][self.bullets[i].pos.x] =
                     self.field.cells[self.bullets[i].pos.y - 1][self.bullets[i].pos.x];
                 self.bullets[i].exist = false;
-                self.erase_rectangle(self.bullets[i].pos);
+                self.erase_rectangle(self.bullets[i].pos, self.bullets[i].owner);
 
                 self.requested_sounds.push("hit.wav");
             }
@@ -352,54 +539,26 @@ impl Game {
 
     pub fn update_erasing_effects(&mut self) {
         for effect in &mut self.erasing_effects {
+            // 消えきったものは得点を二重に入れないよう飛ばす
+            if !effect.exist {
+                continue;
+            }
             if effect.erase_wait > 0 {
                 effect.erase_wait -= 1;
                 continue;
             }
 
-            self.field.cells[effect.cursor.y][effect.cursor.x] = EMPTY;
-
-            // 渦巻き状に消す
-            if effect.dir == Direction::Right {
-                if effect.cursor.x + 1 < FIELD_W
-                    && self.field.cells[effect.cursor.y][effect.cursor.x + 1] == ERASING
-                {
-                    effect.cursor.x += 1;
-                } else {
-                    effect.dir = Direction::Down;
-                    effect.cursor.y += 1;
-                }
-            } else if effect.dir == Direction::Down {
-                if effect.cursor.y + 1 < FIELD_H
-                    && self.field.cells[effect.cursor.y + 1][effect.cursor.x] == ERASING
-                {
-                    effect.cursor.y += 1;
-                } else {
-                    effect.dir = Direction::Left;
-                    effect.cursor.x -= 1;
-                }
-            } else if effect.dir == Direction::Left {
-                if effect.cursor.x >= 1
-                    && self.field.cells[effect.cursor.y][effect.cursor.x - 1] == ERASING
-                {
-                    effect.cursor.x -= 1;
-                } else {
-                    effect.dir = Direction::Up;
-                    effect.cursor.y -= 1;
-                }
-            } else if effect.dir == Direction::Up {
-                if effect.cursor.y >= 1
-                    && self.field.cells[effect.cursor.y - 1][effect.cursor.x] == ERASING
-                {
-                    effect.cursor.y -= 1;
-                } else {
-                    effect.dir = Direction::Right;
-                    effect.cursor.x += 1;
-                }
-            }
-            if self.field.cells[effect.cursor.y][effect.cursor.x] == EMPTY {
+            if self.erase_style.step(effect, &mut self.field) {
                 effect.exist = false;
-                self.score += effect.erased_block_count * 10 * effect.rectangle.area() as i32;
+                let score = effect.erased_block_count * 10 * effect.rectangle.area() as i32;
+                self.score += score;
+                self.players[effect.owner].score += score;
+                self.events.push(GameEvent::Erased {
+                    rectangle: effect.rectangle,
+                    block_count: effect.erased_block_count,
+                    score,
+                    player: effect.owner,
+                });
 
                 if effect.erased_block_count >= 3 {
                     self.erased_texts.push(ErasedText {
@@ -428,49 +587,54 @@ impl Game {
         }
     }
 
-    pub fn start_move_player(&mut self, command: Command) {
-        if self.move_wait == 0 {
-            match command {
-                Command::Left => {
-                    if self.player_x >= 1 {
-                        self.move_dir = Direction::Left;
-                        self.move_wait = MOVE_WAIT;
-                    }
-                }
-                Command::Right => {
-                    if self.player_x + 1 < FIELD_W {
-                        self.move_dir = Direction::Right;
-                        self.move_wait = MOVE_WAIT;
-                    }
-                }
+    pub fn start_move_player(&mut self, i: usize, command: Command) {
+        if self.players[i].move_wait == 0 {
+            let x = self.players[i].x;
+            let (dir, to_x) = match command {
+                Command::Left if x >= 1 => (Direction::Left, x - 1),
+                Command::Right if x + 1 < FIELD_W => (Direction::Right, x + 1),
                 _ => return,
             };
+            // 他の砲台がいる位置や移動中の砲台の移動先には移動できない
+            let is_blocked = self
+                .players
+                .iter()
+                .enumerate()
+                .any(|(j, p)| j != i && (p.x == to_x || p.destination_x() == to_x));
+            if !is_blocked {
+                self.players[i].move_dir = dir;
+                self.players[i].move_wait = MOVE_WAIT;
+            }
         }
     }
 
-    pub fn shoot(&mut self) {
-        if self.shoot_wait > 0 {
+    pub fn shoot(&mut self, i: usize) {
+        if self.players[i].shoot_wait > 0 {
             return;
         }
-        if self.bullets.len() as i32 >= BULLET_COUNT_MAX {
+        // 弾数の上限はプレイヤーごと
+        let bullet_count = self.bullets.iter().filter(|b| b.owner == i).count();
+        if bullet_count as i32 >= BULLET_COUNT_MAX {
             return;
         }
-        let bullet = Bullet::new(self.player_x);
+        let bullet = Bullet::new(self.players[i].x, i);
         self.bullets.push(bullet);
-        self.shoot_wait = SHOOT_WAIT;
+        self.players[i].shoot_wait = SHOOT_WAIT;
         self.requested_sounds.push("shoot.wav");
     }
 
-    pub fn erase_rectangle(&mut self, bullet_pos: Point) {
-        let r = self
-            .field
+    pub fn erase_rectangle(&mut self, bullet_pos: Point, owner: usize) {
+        // 矩形の探索はビットボードで行う。結果はField::find_rectangle_to_be_erasedと同じ
+        let r = BitField::from_field(&self.field)
             .find_rectangle_to_be_erased(bullet_pos.x, bullet_pos.y);
         if let Some(r) = r {
             let mut block_kinds: HashSet<char> = HashSet::new();
+            let mut blocks = Vec::new();
             for y in r.top..=r.bottom {
                 for x in r.left..=r.right {
                     if self.field.cells[y][x] != EMPTY {
                         block_kinds.insert(self.field.cells[y][x]);
+                        blocks.push((Point::new(x, y), self.field.cells[y][x]));
                     }
                     self.field.cells[y][x] = ERASING;
                 }
@@ -486,10 +650,38 @@ impl Game {
                 erase_wait: ERASE_WAIT,
                 dir: Direction::Up,
                 rectangle: r,
+                owner,
+            });
+            self.events.push(GameEvent::EraseStarted {
+                rectangle: r,
+                block_count: block_count as i32,
+                player: owner,
+                blocks,
             });
         }
     }
 
+    // 同期ずれの検出に使う、ゲームの状態のハッシュ
+    // 乱数は複製から1つ引いた値で状態を代表させる
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StateHasher::new();
+        self.frame.hash(&mut hasher);
+        self.is_over.hash(&mut hasher);
+        self.is_clear.hash(&mut hasher);
+        self.field.hash(&mut hasher);
+        self.next_row.hash(&mut hasher);
+        self.players.hash(&mut hasher);
+        self.scroll_wait.hash(&mut hasher);
+        self.garbage_rows.hash(&mut hasher);
+        self.bullets.hash(&mut hasher);
+        self.erasing_effects.hash(&mut hasher);
+        self.erase_style.hash(&mut hasher);
+        self.erased_texts.hash(&mut hasher);
+        self.score.hash(&mut hasher);
+        self.rng.clone().gen::<u64>().hash(&mut hasher);
+        hasher.finish()
+    }
+
     pub fn get_progress(&self) -> i32 {
         if self.is_clear {
             100
@@ -498,12 +690,13 @@ impl Game {
         }
     }
 
-    pub fn get_sight_pos(&self) -> Option<Point> {
+    pub fn get_sight_pos(&self, i: usize) -> Option<Point> {
+        let player_x = self.players[i].x;
         for y in (0..=(FIELD_H - 2)).rev() {
-            let cell = self.field.cells[y][self.player_x];
+            let cell = self.field.cells[y][player_x];
             if cell != EMPTY && cell != ERASING {
-                if self.field.cells[y + 1][self.player_x] == EMPTY {
-                    return Some(Point::new(self.player_x, y + 1));
+                if self.field.cells[y + 1][player_x] == EMPTY {
+                    return Some(Point::new(player_x, y + 1));
                 } else {
                     return None;
                 }
@@ -522,13 +715,173 @@ impl Game {
     }
 }
 
-pub fn load_commands(filename: &str) -> Vec<Command> {
-    let mut commands = Vec::new();
-    if let Some(content) = std::fs::read_to_string(filename).ok() {
-        for (_, line) in content.lines().enumerate() {
-            let command = Command::from_str(line);
-            commands.push(command);
+// 1行が1フレーム分で、協力プレイではプレイヤーごとのコマンドを空白区切りで並べる
+// '#'で始まる行はシードやハッシュの記録なので読み飛ばす
+pub fn load_commands(filename: &str) -> Vec<Vec<Command>> {
+    match std::fs::read_to_string(filename) {
+        Ok(content) => Replay::parse(&content).commands,
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clone_is_independent() {
+        let recorder = MemoryRecorder::new();
+        let mut game = Game::with_seed(0, Box::new(recorder.clone()));
+        game.set_stage("0123456789abcdef\n  aa\n  aa\n").unwrap();
+        game.update(Command::Shoot);
+
+        let mut lookahead = game.clone();
+        for _ in 0..10 {
+            lookahead.update(Command::Left);
+        }
+
+        assert_eq!(game.frame, 0);
+        assert_eq!(game.players[0].x, FIELD_W / 2);
+        assert_eq!(lookahead.frame, 10);
+        assert!(lookahead.players[0].x < FIELD_W / 2);
+        assert_eq!(recorder.commands(), vec![vec![Command::Shoot]]);
+    }
+
+    #[test]
+    fn test_players_cannot_overlap() {
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.set_player_count(2);
+        assert_eq!(game.players[0].x, 5);
+        assert_eq!(game.players[1].x, 10);
+
+        game.scroll_wait = 1000;
+        game.field.cells[0][0] = 'a';
+        for _ in 0..30 {
+            game.update_players(&[Command::Right, Command::Left]);
+        }
+        assert_eq!(game.players[1].x, game.players[0].x + 1);
+    }
+
+    #[test]
+    fn test_score_goes_to_shooter() {
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.set_player_count(2);
+        game.scroll_wait = 1000;
+        game.field.cells[0][10] = 'a';
+        game.field.cells[0][11] = 'a';
+        game.field.cells[1][11] = 'a';
+
+        game.update_players(&[Command::Shoot, Command::Shoot]);
+        for _ in 0..30 {
+            game.update_players(&[Command::None, Command::None]);
+        }
+
+        assert_eq!(game.players[0].score, 0);
+        assert_eq!(game.players[1].score, 40);
+        assert_eq!(game.score, 40);
+    }
+
+    #[test]
+    fn test_scroll_offset_reaches_next_row() {
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.load_stage("resources/data/stage1.dat");
+        // 空のフィールドは毎フレームスクロールするので、ブロックが入るまで進める
+        while game.is_field_empty() {
+            game.update(Command::None);
+        }
+        assert_eq!(game.scroll_offset(), 0.0);
+        let incoming = game.incoming_row().unwrap();
+
+        let field = game.field.clone();
+        let mut previous = 0.0;
+        for _ in 0..SCROLL_WAIT - 1 {
+            game.update(Command::None);
+            assert!(game.scroll_offset() > previous && game.scroll_offset() < 1.0);
+            assert_eq!(game.field, field);
+            previous = game.scroll_offset();
+        }
+
+        // 1マス分進んだところで実際にスクロールし、進み具合は0に戻る
+        game.update(Command::None);
+        assert_eq!(game.scroll_offset(), 0.0);
+        assert_eq!(game.field.cells[0], incoming);
+        assert_eq!(game.field.cells[1], field.cells[0]);
+    }
+
+    #[test]
+    fn test_scroll_offset_stops_while_erasing() {
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.load_stage("resources/data/stage1.dat");
+        game.field.cells[0][8] = 'a';
+        game.field.cells[0][9] = 'a';
+        game.field.cells[1][9] = 'a';
+        game.scroll_wait = 20;
+        game.update(Command::None);
+        assert!(game.scroll_offset() > 0.0);
+
+        game.update(Command::Shoot);
+        while game.erasing_effects.is_empty() {
+            game.update(Command::None);
         }
+
+        // 消えている間は0に戻らず、その位置で止まる
+        let offset = game.scroll_offset();
+        assert!(offset > 0.0);
+        game.update(Command::None);
+        while !game.erasing_effects.is_empty() {
+            assert_eq!(game.scroll_offset(), offset);
+            game.update(Command::None);
+        }
+    }
+
+    #[test]
+    fn test_scroll_offset_does_not_go_back_across_erase() {
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.load_stage("resources/data/stage1.dat");
+        game.field.cells[0][8] = 'a';
+        game.field.cells[0][9] = 'a';
+        game.field.cells[1][9] = 'a';
+        game.update(Command::Shoot);
+        while game.erasing_effects.is_empty() {
+            game.update(Command::None);
+        }
+        // 消えている途中で次のスクロールの時刻が来るようにする
+        game.scroll_wait = 2;
+
+        let mut waited_while_erasing = false;
+        for _ in 0..SCROLL_WAIT * 2 {
+            let (offset, next_row) = (game.scroll_offset(), game.next_row);
+            game.update(Command::None);
+            waited_while_erasing |= game.scroll_wait == 0 && !game.erasing_effects.is_empty();
+            // 実際にスクロールしたとき以外は、フィールドを下げる量は減らない
+            if game.next_row == next_row {
+                assert!(game.scroll_offset() >= offset);
+            }
+        }
+        assert!(waited_while_erasing);
+        assert!(game.erasing_effects.is_empty());
+    }
+
+    #[test]
+    fn test_stage_chooses_erase_style() {
+        let path = std::env::temp_dir().join(format!("stage-{}.dat", std::process::id()));
+        std::fs::write(&path, "#erase burst\n0123456789abcdef\n  aa\n").unwrap();
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.load_stage(path.to_str().unwrap());
+        std::fs::remove_file(&path).ok();
+
+        // 設定の行はステージの行に数えない
+        assert_eq!(game.erase_style, EraseStyle::Burst);
+        assert_eq!(game.stage.len(), 2);
+        assert_eq!(game.next_row, 1);
+    }
+
+    #[test]
+    fn test_rng_state_is_hashed() {
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        let hash = game.state_hash();
+        assert_eq!(hash, game.state_hash());
+        let _: u32 = game.rng.gen();
+        assert_ne!(hash, game.state_hash());
     }
-    return commands;
 }
@@ -0,0 +1,221 @@
+use serde_json::json;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Lines, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time;
+
+use crate::model::*;
+use crate::recorder::*;
+
+// 2台のGameに同じコマンド列を与えて同期させる協力プレイ用のネットプレイ
+// ホストが1P、参加側が2Pを操作する
+//
+// ホスト -> 参加側: {"type":"hello","seed":123,"input_delay":2}
+// 毎フレーム双方: {"type":"input","frame":5,"command":"Left","hash_frame":2,"hash":"..."}
+//   frameはそのコマンドを使うフレーム（input_delayだけ先）、
+//   hashは送った時点のGameの状態のハッシュで、受け取った側で自分のハッシュと比べる
+pub struct Netplay {
+    writer: TcpStream,
+    lines: Lines<BufReader<TcpStream>>,
+    pub seed: u64,
+    pub input_delay: usize,
+    pub local_player: usize,
+    local_commands: VecDeque<Command>,
+    remote_commands: VecDeque<Command>,
+    pub desync_frame: Option<i32>, // 状態が食い違ったフレーム
+}
+
+impl Netplay {
+    pub fn host(address: &str, input_delay: usize) -> Result<Netplay, String> {
+        let listener = TcpListener::bind(address).map_err(|e| e.to_string())?;
+        println!("Waiting for player 2 on {}", address);
+        let (stream, peer) = listener.accept().map_err(|e| e.to_string())?;
+        println!("Player 2 connected from {}", peer);
+
+        let seed = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .expect("SystemTime before UNIX EPOCH!")
+            .as_secs();
+        Netplay::start_host(stream, seed, input_delay)
+    }
+
+    pub fn join(address: &str) -> Result<Netplay, String> {
+        let stream = TcpStream::connect(address).map_err(|e| e.to_string())?;
+        println!("Connected to {}", address);
+        Netplay::start_join(stream)
+    }
+
+    pub fn start_host(stream: TcpStream, seed: u64, input_delay: usize) -> Result<Netplay, String> {
+        let mut netplay = Netplay::new(stream, seed, input_delay, 0)?;
+        netplay.send(json!({"type": "hello", "seed": seed, "input_delay": input_delay}))?;
+        Ok(netplay)
+    }
+
+    pub fn start_join(stream: TcpStream) -> Result<Netplay, String> {
+        let mut netplay = Netplay::new(stream, 0, 0, 1)?;
+        let hello = netplay.receive()?;
+        if hello["type"] != "hello" {
+            return Err(format!("unexpected message: {}", hello));
+        }
+        let (Some(seed), Some(input_delay)) =
+            (hello["seed"].as_u64(), hello["input_delay"].as_u64())
+        else {
+            return Err(format!("invalid hello: {}", hello));
+        };
+        netplay.seed = seed;
+        netplay.input_delay = input_delay as usize;
+        netplay.local_commands = VecDeque::from(vec![Command::None; input_delay as usize]);
+        netplay.remote_commands = netplay.local_commands.clone();
+        Ok(netplay)
+    }
+
+    fn new(
+        stream: TcpStream,
+        seed: u64,
+        input_delay: usize,
+        local_player: usize,
+    ) -> Result<Netplay, String> {
+        stream.set_nodelay(true).ok();
+        let reader = stream.try_clone().map_err(|e| e.to_string())?;
+        Ok(Netplay {
+            writer: stream,
+            lines: BufReader::new(reader).lines(),
+            seed,
+            input_delay,
+            local_player,
+            local_commands: VecDeque::from(vec![Command::None; input_delay]),
+            remote_commands: VecDeque::from(vec![Command::None; input_delay]),
+            desync_frame: None,
+        })
+    }
+
+    // 双方で同じ状態から始めるGame
+    pub fn new_game(&self, stage: &str, recorder: Box<dyn Recorder>) -> Game {
+        let mut game = Game::with_seed(self.seed, recorder);
+        game.load_stage(stage);
+        game.set_player_count(2);
+        game
+    }
+
+    // 自分のコマンドを送り、相手のコマンドが届くのを待ってから1フレーム進める
+    pub fn update(&mut self, game: &mut Game, command: Command) -> Result<(), String> {
+        let hash = game.state_hash();
+        self.send(json!({
+            "type": "input",
+            "frame": game.frame + 1 + self.input_delay as i32,
+            "command": format!("{:?}", command),
+            "hash_frame": game.frame,
+            "hash": format!("{:016x}", hash),
+        }))?;
+        self.local_commands.push_back(command);
+
+        let message = self.receive()?;
+        if message["type"] != "input" {
+            return Err(format!("unexpected message: {}", message));
+        }
+        if message["hash_frame"] != game.frame || message["hash"] != format!("{:016x}", hash) {
+            self.desync_frame = Some(game.frame);
+            return Err(format!("desync detected at frame {}", game.frame));
+        }
+        let remote = message["command"].as_str().unwrap_or("None");
+        self.remote_commands.push_back(Command::from_str(remote));
+
+        let local = self.local_commands.pop_front().unwrap();
+        let remote = self.remote_commands.pop_front().unwrap();
+        let commands = if self.local_player == 0 {
+            [local, remote]
+        } else {
+            [remote, local]
+        };
+        game.update_players(&commands);
+        Ok(())
+    }
+
+    fn send(&mut self, message: serde_json::Value) -> Result<(), String> {
+        writeln!(self.writer, "{}", message).map_err(|e| e.to_string())?;
+        self.writer.flush().map_err(|e| e.to_string())
+    }
+
+    fn receive(&mut self) -> Result<serde_json::Value, String> {
+        let line = self
+            .lines
+            .next()
+            .ok_or("peer disconnected")?
+            .map_err(|e| e.to_string())?;
+        serde_json::from_str(&line).map_err(|e| format!("invalid message: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const STAGE: &str = "resources/data/stage1.dat";
+
+    // 2台のGameを別スレッドで動かし、それぞれの(Game, 結果, 同期ずれのフレーム)を返す
+    fn run_pair(
+        frames: usize,
+        perturb_at: Option<i32>,
+    ) -> [(Game, Result<(), String>, Option<i32>); 2] {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let commands = [
+            Command::Shoot,
+            Command::Left,
+            Command::Shoot,
+            Command::Right,
+        ];
+
+        let joiner = thread::spawn(move || {
+            let mut netplay = Netplay::start_join(TcpStream::connect(address).unwrap()).unwrap();
+            let mut game = netplay.new_game(STAGE, Box::new(NullRecorder));
+            let mut result = Ok(());
+            for i in 0..frames {
+                if Some(game.frame) == perturb_at {
+                    game.score += 1;
+                }
+                result = netplay.update(&mut game, commands[(i + 1) % commands.len()]);
+                if result.is_err() {
+                    break;
+                }
+            }
+            (game, result, netplay.desync_frame)
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut netplay = Netplay::start_host(stream, 7, 2).unwrap();
+        let mut game = netplay.new_game(STAGE, Box::new(NullRecorder));
+        let mut result = Ok(());
+        for i in 0..frames {
+            result = netplay.update(&mut game, commands[i % commands.len()]);
+            if result.is_err() {
+                break;
+            }
+        }
+        let host = (game, result, netplay.desync_frame);
+        drop(netplay);
+        [host, joiner.join().unwrap()]
+    }
+
+    #[test]
+    fn test_games_stay_in_sync() {
+        let [(host, host_result, _), (joiner, joiner_result, _)] = run_pair(300, None);
+        assert_eq!(host_result, Ok(()));
+        assert_eq!(joiner_result, Ok(()));
+        assert_eq!(host.frame, 299);
+        assert_eq!(host.state_hash(), joiner.state_hash());
+        assert_eq!(host.field, joiner.field);
+        assert_ne!(host.players[0].x, host.players[1].x);
+    }
+
+    #[test]
+    fn test_desync_reports_frame() {
+        let [(_, host_result, host_desync), (_, joiner_result, joiner_desync)] =
+            run_pair(300, Some(100));
+        assert_eq!(host_result, Err("desync detected at frame 100".to_string()));
+        assert_eq!(host_desync, Some(100));
+        assert_eq!(joiner_desync, Some(100));
+        assert!(joiner_result.is_err());
+    }
+}
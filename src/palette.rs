@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+
+use crate::field::*;
+use crate::model::ERASING;
+
+// 隣り合うブロックを必ず塗り分けるのに必要な色の数
+// ブロックの隣接関係は平面グラフなので、次数の小さい順に塗れば6色で足りる
+pub const MIN_COLORS: usize = 6;
+
+pub const PALETTE_DIR: &str = "resources/palette";
+
+// 色ごとに添える記号。色を見分けにくくても記号で区別できる
+pub const GLYPHS: [char; 8] = ['o', 'x', '+', '#', '=', '%', '~', '^'];
+
+// ブロックの配色。resources/palette/NAME.txtに1行1色で16進のRRGGBBを書く
+// '#'で始まる行と空行は読み飛ばす
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<(u8, u8, u8)>,
+}
+
+impl Palette {
+    pub fn load(name: &str) -> Result<Palette, String> {
+        let filename = format!("{}/{}.txt", PALETTE_DIR, name);
+        let text =
+            std::fs::read_to_string(&filename).map_err(|e| format!("{}: {}", filename, e))?;
+        Palette::parse(name, &text).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    pub fn parse(name: &str, text: &str) -> Result<Palette, String> {
+        let mut colors = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rgb = u32::from_str_radix(line, 16)
+                .ok()
+                .filter(|_| line.len() == 6)
+                .ok_or(format!("invalid color: {}", line))?;
+            colors.push(((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+        }
+        if colors.len() < MIN_COLORS {
+            return Err(format!(
+                "{} colors given, at least {} required",
+                colors.len(),
+                MIN_COLORS
+            ));
+        }
+        Ok(Palette {
+            name: name.to_string(),
+            colors,
+        })
+    }
+}
+
+// フィールドのブロックへの色の割り当て
+// 同じ文字がつながった範囲を1つのブロックとし、隣り合うブロックには違う色を割り当てる
+// 前のフレームと同じ色を優先するので、スクロールしても色がなるべく変わらない
+#[derive(Debug, Clone)]
+pub struct BlockColors {
+    pub palette: Palette,
+    pub glyphs: bool, // ブロックに記号も描くか
+    cells: [[Option<usize>; FIELD_W]; FIELD_H],
+    previous: HashMap<Cell, usize>,
+}
+
+impl BlockColors {
+    pub fn new(palette: Palette) -> BlockColors {
+        BlockColors {
+            palette,
+            glyphs: false,
+            cells: [[None; FIELD_W]; FIELD_H],
+            previous: HashMap::new(),
+        }
+    }
+
+    // 空白と消去中のマスはNone
+    pub fn color(&self, x: usize, y: usize) -> Option<(u8, u8, u8)> {
+        self.cells[y][x].map(|i| self.palette.colors[i])
+    }
+
+    // 記号を描かないときや空白と消去中のマスはNone
+    pub fn glyph(&self, x: usize, y: usize) -> Option<char> {
+        self.cells[y][x]
+            .filter(|_| self.glyphs)
+            .map(|i| GLYPHS[i % GLYPHS.len()])
+    }
+
+    // まだフィールドに入っていないブロックの色と記号。同じ文字のブロックがあればその色を使う
+    pub fn color_of(&self, cell: Cell) -> (u8, u8, u8) {
+        self.palette.colors[self.index_of(cell)]
+    }
+
+    pub fn glyph_of(&self, cell: Cell) -> Option<char> {
+        Some(GLYPHS[self.index_of(cell) % GLYPHS.len()]).filter(|_| self.glyphs)
+    }
+
+    fn index_of(&self, cell: Cell) -> usize {
+        let n = self.palette.colors.len();
+        self.previous
+            .get(&cell)
+            .copied()
+            .unwrap_or(cell as usize % n)
+    }
+
+    // 描画する前に毎フレーム呼ぶ
+    pub fn assign(&mut self, field: &Field) {
+        let (regions, region_cells) = find_regions(field);
+
+        let mut neighbors = vec![Vec::new(); region_cells.len()];
+        for y in 0..FIELD_H {
+            for x in 0..FIELD_W {
+                let Some(a) = regions[y][x] else { continue };
+                let right = if x + 1 < FIELD_W {
+                    regions[y][x + 1]
+                } else {
+                    None
+                };
+                let below = if y + 1 < FIELD_H {
+                    regions[y + 1][x]
+                } else {
+                    None
+                };
+                for b in [right, below].into_iter().flatten() {
+                    if a != b && !neighbors[a].contains(&b) {
+                        neighbors[a].push(b);
+                        neighbors[b].push(a);
+                    }
+                }
+            }
+        }
+
+        let n = self.palette.colors.len();
+        let mut colors: Vec<Option<usize>> = vec![None; region_cells.len()];
+        for region in coloring_order(&neighbors) {
+            let used: Vec<usize> = neighbors[region]
+                .iter()
+                .filter_map(|&other| colors[other])
+                .collect();
+            let cell = region_cells[region];
+            let default = cell as usize % n;
+            // 前のフレームの色、既定の色、空いている色の順に試す
+            let mut candidates = self
+                .previous
+                .get(&cell)
+                .copied()
+                .into_iter()
+                .chain(std::iter::once(default))
+                .chain(0..n);
+            colors[region] = Some(candidates.find(|i| !used.contains(i)).unwrap_or(default));
+        }
+
+        self.previous.clear();
+        for (region, &cell) in region_cells.iter().enumerate() {
+            self.previous.insert(cell, colors[region].unwrap());
+        }
+        self.cells = regions.map(|row| row.map(|region| region.and_then(|region| colors[region])));
+    }
+}
+
+type Regions = [[Option<usize>; FIELD_W]; FIELD_H];
+
+// 同じ文字が上下左右につながった範囲に番号を振る。番号ごとの文字も返す
+fn find_regions(field: &Field) -> (Regions, Vec<Cell>) {
+    let mut regions = [[None; FIELD_W]; FIELD_H];
+    let mut region_cells = Vec::new();
+    for y in 0..FIELD_H {
+        for x in 0..FIELD_W {
+            let cell = field.cells[y][x];
+            if cell == EMPTY || cell == ERASING || regions[y][x].is_some() {
+                continue;
+            }
+            let region = region_cells.len();
+            region_cells.push(cell);
+            let mut stack = vec![(x, y)];
+            regions[y][x] = Some(region);
+            while let Some((x, y)) = stack.pop() {
+                let mut next = Vec::new();
+                if x > 0 {
+                    next.push((x - 1, y));
+                }
+                if x + 1 < FIELD_W {
+                    next.push((x + 1, y));
+                }
+                if y > 0 {
+                    next.push((x, y - 1));
+                }
+                if y + 1 < FIELD_H {
+                    next.push((x, y + 1));
+                }
+                for (nx, ny) in next {
+                    if field.cells[ny][nx] == cell && regions[ny][nx].is_none() {
+                        regions[ny][nx] = Some(region);
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+        }
+    }
+    (regions, region_cells)
+}
+
+// 隣接の少ないものから順に取り除き、その逆順に塗る（smallest-last順）
+// 平面グラフでは塗るときに塗り済みの隣が5つ以下になる
+fn coloring_order(neighbors: &[Vec<usize>]) -> Vec<usize> {
+    let mut degrees: Vec<usize> = neighbors.iter().map(|n| n.len()).collect();
+    let mut removed = vec![false; neighbors.len()];
+    let mut order = Vec::with_capacity(neighbors.len());
+    for _ in 0..neighbors.len() {
+        let region = (0..neighbors.len())
+            .filter(|&i| !removed[i])
+            .min_by_key(|&i| degrees[i])
+            .unwrap();
+        removed[region] = true;
+        for &other in &neighbors[region] {
+            degrees[other] -= 1;
+        }
+        order.push(region);
+    }
+    order.reverse();
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn assert_neighbors_differ(colors: &BlockColors, field: &Field) {
+        for y in 0..FIELD_H {
+            for x in 0..FIELD_W {
+                let cell = field.cells[y][x];
+                for (nx, ny) in [(x + 1, y), (x, y + 1)] {
+                    if nx < FIELD_W && ny < FIELD_H && field.cells[ny][nx] != cell {
+                        if let (Some(a), Some(b)) = (colors.color(x, y), colors.color(nx, ny)) {
+                            assert_ne!(a, b, "({}, {}) and ({}, {})", x, y, nx, ny);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_palettes() {
+        for name in ["default", "deuteranopia", "protanopia", "high-contrast"] {
+            let palette = Palette::load(name).unwrap();
+            assert!(palette.colors.len() >= MIN_COLORS);
+        }
+        assert_eq!(
+            Palette::load("default").unwrap().colors[1],
+            (0xFF, 0x80, 0x80)
+        );
+        assert!(Palette::parse("few", "FF0000\n00FF00\n").is_err());
+        assert!(Palette::parse("bad", "FF00\n").is_err());
+    }
+
+    #[test]
+    fn test_neighbors_get_different_colors() {
+        // 'a'と'g'はどちらも既定では同じ色になる
+        assert_eq!('a' as usize % 6, 'g' as usize % 6);
+        #[rustfmt::skip]
+        let text = concat!(
+            "aaag\n",
+            "aaag\n",
+            "  *g\n",
+        );
+        let field = Field::from_text(text);
+        let mut colors = BlockColors::new(Palette::load("default").unwrap());
+        colors.assign(&field);
+        assert_neighbors_differ(&colors, &field);
+        assert_eq!(colors.color(2, 2), None);
+        assert_eq!(colors.color(0, 0), colors.color(2, 1));
+
+        assert_eq!(colors.glyph(0, 0), None);
+        colors.glyphs = true;
+        assert_ne!(colors.glyph(0, 0), colors.glyph(3, 0));
+        assert_eq!(colors.glyph(2, 2), None);
+    }
+
+    #[test]
+    fn test_colors_are_kept_while_scrolling() {
+        let mut colors = BlockColors::new(Palette::load("default").unwrap());
+        let field = Field::from_text("bbbb\n");
+        colors.assign(&field);
+        let b = colors.color(0, 0);
+
+        // 同じ色になるブロックが上から入ってきても、既にあるブロックの色は変わらない
+        let field = Field::from_text("hhhh\nbbbb\n");
+        colors.assign(&field);
+        assert_eq!(colors.color(0, 1), b);
+        assert_ne!(colors.color(0, 0), b);
+        assert_eq!(Some(colors.color_of('b')), b);
+        assert_eq!(
+            colors.color_of('z'),
+            colors.palette.colors['z' as usize % 6]
+        );
+    }
+
+    fn random_field() -> impl Strategy<Value = Field> {
+        proptest::collection::vec(0..4usize, FIELD_W * FIELD_H).prop_map(|ids| {
+            let mut field = Field::new();
+            for (i, id) in ids.into_iter().enumerate() {
+                field.cells[i / FIELD_W][i % FIELD_W] = [EMPTY, 'a', 'g', 'm'][id];
+            }
+            field
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_neighbors_get_different_colors(field in random_field()) {
+            let mut colors = BlockColors::new(Palette::load("default").unwrap());
+            colors.assign(&field);
+            assert_neighbors_differ(&colors, &field);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::erase::EraseStyle;
+use crate::model::Command;
+
+// Gameに入力されたコマンドの記録先
+pub trait Recorder: Debug + Send {
+    // 1フレーム分のコマンド（プレイヤーごと）を記録する
+    fn record(&mut self, commands: &[Command]);
+
+    // 乱数のシードを記録する
+    fn record_seed(&mut self, _seed: u64) {}
+
+    // 読み込んだステージのハッシュを記録する
+    fn record_stage(&mut self, _hash: u64) {}
+
+    // 消去のアニメーションを選んだ
+    fn record_erase_style(&mut self, _style: EraseStyle) {}
+
+    // frameを終えた時点の状態のハッシュを記録する
+    fn record_hash(&mut self, _frame: i32, _hash: u64) {}
+
+    // 練習モードでframeまで巻き戻した
+    fn record_rewind(&mut self, _frame: i32) {}
+
+    // Gameを複製したときの記録先
+    // 複製したGameは先読みに使うので、元の記録先に書き込んではいけない
+    // FileRecorderの複製はNullRecorderになり、複製したGameの入力はどこにも残らない
+    fn clone_box(&self) -> Box<dyn Recorder>;
+}
+
+impl Clone for Box<dyn Recorder> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+// ファイルに1行1フレームで書き出す（replay.datと同じ形式）
+// シードとステージ、消去のアニメーション、状態のハッシュ、巻き戻しは'#'で始まる行に書く
+// 書き出し先はファイルに限らず、Writeなら何でもよい
+#[derive(Debug)]
+pub struct FileRecorder<W: Write = File> {
+    file: W,
+}
+
+impl FileRecorder {
+    pub fn create(filename: &str) -> std::io::Result<FileRecorder> {
+        Ok(FileRecorder::new(File::create(filename)?))
+    }
+}
+
+impl<W: Write> FileRecorder<W> {
+    pub fn new(file: W) -> FileRecorder<W> {
+        FileRecorder { file }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        self.file.write_all(format!("{}\n", line).as_bytes()).ok();
+        self.file.flush().ok();
+    }
+}
+
+impl<W: Write + Debug + Send + 'static> Recorder for FileRecorder<W> {
+    fn record(&mut self, commands: &[Command]) {
+        let line: Vec<String> = commands.iter().map(|c| format!("{:?}", c)).collect();
+        self.write_line(&line.join(" "));
+    }
+
+    fn record_seed(&mut self, seed: u64) {
+        self.write_line(&format!("#seed {}", seed));
+    }
+
+    fn record_stage(&mut self, hash: u64) {
+        self.write_line(&format!("#stage {:016x}", hash));
+    }
+
+    fn record_erase_style(&mut self, style: EraseStyle) {
+        self.write_line(&format!("#erase {}", style.name()));
+    }
+
+    fn record_hash(&mut self, frame: i32, hash: u64) {
+        self.write_line(&format!("#hash {} {:016x}", frame, hash));
+    }
+
+    fn record_rewind(&mut self, frame: i32) {
+        self.write_line(&format!("#rewind {}", frame));
+    }
+
+    // 先読みなどで複製したGameが同じファイルに書き込まないように、複製先では記録しない
+    fn clone_box(&self) -> Box<dyn Recorder> {
+        Box::new(NullRecorder)
+    }
+}
+
+// メモリ上に記録する
+// clone()したものは同じ記録を共有するので、Gameに渡す前にclone()しておけば後から読める
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRecorder {
+    commands: Arc<Mutex<Vec<Vec<Command>>>>,
+}
+
+impl MemoryRecorder {
+    pub fn new() -> MemoryRecorder {
+        MemoryRecorder::default()
+    }
+
+    pub fn commands(&self) -> Vec<Vec<Command>> {
+        self.commands.lock().unwrap().clone()
+    }
+}
+
+impl Recorder for MemoryRecorder {
+    fn record(&mut self, commands: &[Command]) {
+        self.commands.lock().unwrap().push(commands.to_vec());
+    }
+
+    // 複製したGameの記録は元のGameの記録と混ざらないように分ける
+    fn clone_box(&self) -> Box<dyn Recorder> {
+        Box::new(MemoryRecorder {
+            commands: Arc::new(Mutex::new(self.commands())),
+        })
+    }
+}
+
+// 何も記録しない
+#[derive(Debug, Clone, Default)]
+pub struct NullRecorder;
+
+impl Recorder for NullRecorder {
+    fn record(&mut self, _commands: &[Command]) {}
+
+    fn clone_box(&self) -> Box<dyn Recorder> {
+        Box::new(NullRecorder)
+    }
+}
@@ -0,0 +1,293 @@
+use std::fmt;
+
+use crate::erase::*;
+use crate::model::*;
+use crate::recorder::*;
+
+// コマンドログ（リプレイ）の中身
+//
+// #seed 1700000000        乱数のシード
+// #stage 5d1c0b7e2f3a4968  ステージのハッシュ
+// #erase wipe             消去のアニメーション（なければ渦巻き）
+// Left                    1行1フレームのコマンド。協力プレイではプレイヤーごとに空白区切り
+// #hash 0 af63dc4c8601ec8c  そのフレームを終えた時点の状態のハッシュ
+// #rewind 120             練習モードでそのフレームまで巻き戻した（それより前の行にある後のフレームは取り消し）
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Replay {
+    pub seed: Option<u64>,
+    pub stage_hash: Option<u64>,
+    pub erase_style: Option<EraseStyle>,
+    pub commands: Vec<Vec<Command>>,
+    pub hashes: Vec<(i32, u64)>, // (フレーム, ハッシュ)
+    pub rewinds: Vec<i32>,       // 巻き戻した先のフレーム
+}
+
+// 再生した状態のハッシュが記録と食い違った
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Desync {
+    pub frame: i32,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "hash differs at frame {} (expected {:016x}, got {:016x})",
+            self.frame, self.expected, self.actual
+        )
+    }
+}
+
+impl Replay {
+    pub fn load(filename: &str) -> Result<Replay, String> {
+        let content =
+            std::fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        Ok(Replay::parse(&content))
+    }
+
+    pub fn parse(content: &str) -> Replay {
+        let mut replay = Replay::default();
+        for line in content.lines() {
+            let Some(meta) = line.strip_prefix('#') else {
+                let command = line.split_whitespace().map(Command::from_str).collect();
+                replay.commands.push(command);
+                continue;
+            };
+            let words: Vec<&str> = meta.split_whitespace().collect();
+            match words.as_slice() {
+                ["seed", seed] => replay.seed = seed.parse().ok(),
+                ["stage", hash] => replay.stage_hash = u64::from_str_radix(hash, 16).ok(),
+                ["erase", name] => replay.erase_style = EraseStyle::from_name(name).ok(),
+                ["rewind", frame] => {
+                    // 巻き戻した先より後のコマンドとハッシュは取り消されたので捨てる
+                    if let Ok(frame) = frame.parse::<i32>() {
+                        replay.commands.truncate((frame + 1).max(0) as usize);
+                        replay.hashes.retain(|&(f, _)| f <= frame);
+                        replay.rewinds.push(frame);
+                    }
+                }
+                ["hash", frame, hash] => {
+                    if let (Ok(frame), Ok(hash)) = (frame.parse(), u64::from_str_radix(hash, 16)) {
+                        replay.hashes.push((frame, hash));
+                    }
+                }
+                _ => {}
+            }
+        }
+        replay
+    }
+
+    // 記録したときと同じ状態から始めるGame
+    pub fn new_game(&self, stage: &str) -> Result<Game, String> {
+        let seed = self.seed.ok_or("replay has no seed")?;
+        let mut game = Game::with_seed(seed, Box::new(NullRecorder));
+        game.try_load_stage(stage)?;
+        if let Some(style) = self.erase_style {
+            game.erase_style = style;
+        }
+        let player_count = self.commands.iter().map(|c| c.len()).max().unwrap_or(1);
+        if player_count > 1 {
+            game.set_player_count(player_count);
+        }
+        Ok(game)
+    }
+
+    // 記録されたコマンドでgameをframeまで進める。記録が尽きたらCommand::Noneで進める
+    pub fn play_to(&self, game: &mut Game, frame: i32) {
+        while game.frame < frame {
+            let next = (game.frame + 1) as usize;
+            let commands = self.commands.get(next).cloned().unwrap_or_default();
+            game.update_players(&commands);
+            game.requested_sounds.clear();
+            game.events.clear();
+        }
+    }
+
+    // コマンドを順に与えて、記録されたハッシュと一致するか確かめる
+    // 一致すれば確かめたハッシュの数を、食い違えば最初に食い違ったフレームを返す
+    pub fn verify(&self, mut game: Game) -> Result<usize, Desync> {
+        let mut hashes = self.hashes.iter().peekable();
+        for commands in &self.commands {
+            game.update_players(commands);
+            while let Some(&&(frame, expected)) = hashes.peek() {
+                if frame > game.frame {
+                    break;
+                }
+                hashes.next();
+                let actual = game.state_hash();
+                if frame == game.frame && actual != expected {
+                    return Err(Desync {
+                        frame,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+        Ok(self.hashes.len() - hashes.count())
+    }
+}
+
+// テストでコマンドログをファイルに書かずに記録し、リプレイとして読み直す
+#[cfg(test)]
+pub mod testing {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    pub const STAGE: &str = "resources/data/stage1.dat";
+
+    // 書き込まれたコマンドログ。clone()したものは同じ中身を共有するので、Gameに渡した後でも読める
+    #[derive(Debug, Clone, Default)]
+    pub struct MemoryLog(Arc<Mutex<Vec<u8>>>);
+
+    impl MemoryLog {
+        pub fn content(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+
+        pub fn replay(&self) -> Replay {
+            Replay::parse(&self.content())
+        }
+    }
+
+    impl Write for MemoryLog {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // STAGEを読み込み、コマンドログをMemoryLogに記録するGame
+    pub fn recording_game(seed: u64) -> (Game, MemoryLog) {
+        let log = MemoryLog::default();
+        let mut game = Game::with_seed(seed, Box::new(FileRecorder::new(log.clone())));
+        game.load_stage(STAGE);
+        (game, log)
+    }
+
+    // patternのコマンドを繰り返してframesフレーム進める
+    pub fn play_pattern(game: &mut Game, pattern: &[Command], frames: usize) {
+        for i in 0..frames {
+            game.update(pattern[i % pattern.len()]);
+        }
+    }
+
+    // patternのコマンドを繰り返して、ゲームオーバーかクリアになるまで進める
+    pub fn play_until_finished(game: &mut Game, pattern: &[Command]) {
+        let mut i = 0;
+        while !game.is_over && !game.is_clear {
+            game.update(pattern[i % pattern.len()]);
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::*;
+    use super::*;
+    use crate::field::*;
+
+    // 7フレームに1回撃つだけのリプレイ
+    fn record() -> MemoryLog {
+        let (mut game, log) = recording_game(3);
+        game.hash_interval = 10;
+        let mut pattern = vec![Command::None; 7];
+        pattern[0] = Command::Shoot;
+        play_pattern(&mut game, &pattern, 100);
+        log
+    }
+
+    #[test]
+    fn test_verify_recorded_replay() {
+        let content = record().content();
+        assert!(content.starts_with("#seed 3\n#stage "));
+
+        let replay = Replay::parse(&content);
+        assert_eq!(replay.seed, Some(3));
+        let game = replay.new_game(STAGE).unwrap();
+        assert_eq!(replay.stage_hash, Some(game.stage_hash()));
+        assert_eq!(replay.commands.len(), 100);
+        assert_eq!(replay.hashes.len(), 10);
+        assert_eq!(replay.hashes[1].0, 10);
+        assert_eq!(replay.verify(replay.new_game(STAGE).unwrap()), Ok(10));
+
+        // 25フレーム目のコマンドを書き換えると、その後の最初のハッシュで食い違う
+        let mut tampered = replay.clone();
+        tampered.commands[25] = vec![Command::Left];
+        let desync = tampered
+            .verify(tampered.new_game(STAGE).unwrap())
+            .unwrap_err();
+        assert_eq!(desync.frame, 30);
+        assert_eq!(desync.expected, replay.hashes[3].1);
+    }
+
+    #[test]
+    fn test_missing_stage_is_an_error() {
+        let replay = record().replay();
+        let error = replay.new_game("resources/data/missing.dat").unwrap_err();
+        assert!(error.starts_with("resources/data/missing.dat: "));
+    }
+
+    #[test]
+    fn test_play_to_frame() {
+        let replay = record().replay();
+
+        let mut game = replay.new_game(STAGE).unwrap();
+        replay.play_to(&mut game, 50);
+        assert_eq!(game.frame, 50);
+        assert_eq!(game.state_hash(), replay.hashes[5].1);
+
+        // 記録の先はCommand::Noneで進める
+        replay.play_to(&mut game, 150);
+        assert_eq!(game.frame, 150);
+    }
+
+    #[test]
+    fn test_hash_of_last_frame_is_recorded() {
+        let (mut game, log) = recording_game(3);
+        game.hash_interval = 10;
+        play_pattern(&mut game, &[Command::None], 15);
+        game.field.cells[FIELD_H - 1][0] = 'z';
+        game.update(Command::None);
+        assert!(game.is_over);
+
+        // 間隔の途中で終わっても、最後のフレームのハッシュがある
+        assert_eq!(game.frame, 15);
+        assert_eq!(
+            log.replay().hashes.last(),
+            Some(&(game.frame, game.state_hash()))
+        );
+    }
+
+    #[test]
+    fn test_erase_style_is_replayed() {
+        let (mut game, log) = recording_game(3);
+        game.set_erase_style(EraseStyle::Instant);
+        game.hash_interval = 10;
+        let pattern = [
+            Command::Shoot,
+            Command::Left,
+            Command::Shoot,
+            Command::Right,
+        ];
+        play_pattern(&mut game, &pattern, 200);
+        assert!(game.score > 0);
+        let replay = log.replay();
+
+        assert_eq!(replay.erase_style, Some(EraseStyle::Instant));
+        assert_eq!(replay.verify(replay.new_game(STAGE).unwrap()), Ok(20));
+
+        // 渦巻きで再生すると消え方が変わって食い違う
+        let mut spiral = replay.clone();
+        spiral.erase_style = None;
+        assert!(spiral.verify(spiral.new_game(STAGE).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+
+use crate::model::*;
+
+// 練習モードで時間を巻き戻すための、直近のGameのスナップショット
+pub struct Rewind {
+    snapshots: VecDeque<Game>,
+    capacity: usize,
+    is_rewinding: bool,
+}
+
+impl Rewind {
+    // capacityフレーム分までさかのぼれる
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+            is_rewinding: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    // 1フレーム進めるたびに呼ぶ。古いものから捨てる
+    // 複製したGameはコマンドログに書き込まない。戻したときに音が鳴り直さないように音とイベントは除く
+    pub fn push(&mut self, game: &Game) {
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        let mut snapshot = game.clone();
+        snapshot.requested_sounds.clear();
+        snapshot.events.clear();
+        self.snapshots.push_back(snapshot);
+    }
+
+    // 1フレーム前の状態に戻す。コマンドログの記録先はそのまま使い続ける
+    // これ以上さかのぼれなければfalse
+    pub fn step_back(&mut self, game: &mut Game) -> bool {
+        // 最新のスナップショットは今の状態なので、その1つ前に戻す
+        if self.snapshots.len() < 2 {
+            return false;
+        }
+        self.snapshots.pop_back();
+        let mut snapshot = self.snapshots.back().unwrap().clone();
+        std::mem::swap(&mut snapshot.recorder, &mut game.recorder);
+        *game = snapshot;
+        self.is_rewinding = true;
+        true
+    }
+
+    // 巻き戻しを終えて再開するときに呼ぶ
+    // どこまで戻したかをコマンドログに残し、そのリプレイを練習として扱えるようにする
+    pub fn resume(&mut self, game: &mut Game) {
+        if self.is_rewinding {
+            game.recorder.record_rewind(game.frame);
+            self.is_rewinding = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::testing::*;
+
+    #[test]
+    fn test_rewind_and_resume() {
+        let (mut game, log) = recording_game(0);
+
+        let mut rewind = Rewind::new(50);
+        let mut hashes = Vec::new();
+        rewind.push(&game);
+        for i in 0..100 {
+            game.update(if i % 3 == 0 {
+                Command::Shoot
+            } else {
+                Command::Left
+            });
+            rewind.push(&game);
+            hashes.push(game.state_hash());
+        }
+        assert_eq!(rewind.len(), 50);
+
+        for _ in 0..20 {
+            assert!(rewind.step_back(&mut game));
+        }
+        assert_eq!(game.frame, 79);
+        assert_eq!(game.state_hash(), hashes[79]);
+
+        // 残りを全部戻すと、覚えている一番古いフレームで止まる
+        while rewind.step_back(&mut game) {}
+        assert_eq!(game.frame, 50);
+
+        rewind.resume(&mut game);
+        game.update(Command::None);
+        rewind.resume(&mut game);
+
+        let replay = log.replay();
+        assert_eq!(replay.rewinds, vec![50]);
+        assert_eq!(replay.commands.len(), 52);
+    }
+
+    #[test]
+    fn test_replay_rewound_log() {
+        let (mut game, log) = recording_game(0);
+        game.hash_interval = 10;
+
+        let mut rewind = Rewind::new(50);
+        rewind.push(&game);
+        for i in 0..80 {
+            game.update(if i % 3 == 0 {
+                Command::Shoot
+            } else {
+                Command::Left
+            });
+            rewind.push(&game);
+        }
+        for _ in 0..25 {
+            rewind.step_back(&mut game);
+        }
+        rewind.resume(&mut game);
+
+        // 巻き戻した後は別のコマンドで進める
+        for i in 0..40 {
+            game.update(if i % 4 == 0 {
+                Command::Shoot
+            } else {
+                Command::Right
+            });
+            rewind.push(&game);
+        }
+        let (frame, hash) = (game.frame, game.state_hash());
+
+        let replay = log.replay();
+        assert_eq!(replay.rewinds, vec![54]);
+        assert_eq!(replay.commands.len(), frame as usize + 1);
+        assert!(replay.hashes.windows(2).all(|w| w[0].0 < w[1].0));
+
+        // 取り消したフレームを除いて再生すると、実際に遊んだ最後の状態になる
+        let mut replayed = replay.new_game(STAGE).unwrap();
+        replay.play_to(&mut replayed, frame);
+        assert_eq!(replayed.state_hash(), hash);
+        let replayed = replay.new_game(STAGE).unwrap();
+        assert_eq!(replay.verify(replayed), Ok(replay.hashes.len()));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::field::*;
+use crate::model::*;
+use crate::recorder::*;
+
+// ゲームの挙動をテストするための小さな台本
+// ステージ全体やリプレイを読み込まずに、フィールドとステージの末尾だけを指定してGameを動かす
+//
+//     let mut scenario = Scenario::new(field, stage_tail);
+//     scenario.run("Shoot None*20");
+//     scenario.assert_field(expected);
+pub struct Scenario {
+    pub game: Game,
+}
+
+impl Scenario {
+    // stage_tailはステージデータの最後の数行で、下の行から順にフィールドに入ってくる
+    pub fn new(field_text: &str, stage_tail: &str) -> Scenario {
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.field = Field::from_text(field_text);
+
+        // ステージの1行目は見出しでフィールドには入らないので、空行を足しておく
+        game.stage = std::iter::once("")
+            .chain(stage_tail.lines())
+            .map(|line| format!("{:<width$}", line, width = FIELD_W)[0..FIELD_W].to_string())
+            .collect();
+        game.next_row = game.stage.len() - 1;
+
+        Scenario { game }
+    }
+
+    // 砲台の位置を指定する。人数も指定した数になる
+    pub fn set_players(&mut self, xs: &[usize]) -> &mut Self {
+        self.game.set_player_count(xs.len());
+        for (player, &x) in self.game.players.iter_mut().zip(xs) {
+            player.x = x;
+        }
+        self
+    }
+
+    // 空白区切りで1フレームずつコマンドを与える
+    // "None*30"で30フレーム繰り返し、"Shoot,Left"で協力プレイの1P,2Pのコマンドを並べる
+    pub fn run(&mut self, commands: &str) -> &mut Self {
+        for word in commands.split_whitespace() {
+            let (word, count) = match word.split_once('*') {
+                Some((word, count)) => (word, count.parse().expect("invalid repeat count")),
+                None => (word, 1),
+            };
+            let frame: Vec<Command> = word.split(',').map(parse_command).collect();
+            for _ in 0..count {
+                self.game.update_players(&frame);
+            }
+        }
+        self
+    }
+
+    // 省略した行や行末は空白とみなして比べる
+    pub fn assert_field(&self, expected: &str) {
+        let expected = Field::from_text(expected);
+        if self.game.field != expected {
+            panic!(
+                "field differs at frame {}\nexpected:\n{}actual:\n{}",
+                self.game.frame,
+                expected.to_text(),
+                self.game.field.to_text()
+            );
+        }
+    }
+}
+
+fn parse_command(word: &str) -> Command {
+    match word {
+        "None" | "Left" | "Right" | "Up" | "Shoot" => Command::from_str(word),
+        _ => panic!("unknown command: {}", word),
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use crate::field::*;
+
+pub const SKIN_DIR: &str = "resources/skin";
+
+// ブロックの境目の16通りのタイルを並べる順番に使うビット
+const TOP: usize = 1;
+const RIGHT: usize = 2;
+const BOTTOM: usize = 4;
+const LEFT: usize = 8;
+
+// スプライトシートの中の1枚のタイル（ピクセル単位）
+pub type Tile = (i32, i32, u32, u32);
+
+// ブロックや砲台を描くスプライトシートと、その中のタイルの位置
+// resources/skin/NAME/skin.txtに「名前 タイルのx y ...」を1行ずつ書く（位置はタイル単位）
+// タイルは白で描いておき、ブロックやプレイヤーの色を掛けて使う
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Skin {
+    pub name: String,
+    pub sheet: String, // PNGかBMPのファイル名
+    pub tile_size: u32,
+    block: (u32, u32), // 境目の16通りを横に並べた先頭
+    cannon: (u32, u32),
+    bullet: (u32, u32),
+    erase: Vec<(u32, u32)>, // 消去中のアニメーションのコマ
+}
+
+impl Skin {
+    pub fn load(name: &str) -> Result<Skin, String> {
+        let dir = format!("{}/{}", SKIN_DIR, name);
+        let filename = format!("{}/skin.txt", dir);
+        let text =
+            std::fs::read_to_string(&filename).map_err(|e| format!("{}: {}", filename, e))?;
+        let mut skin = Skin::parse(name, &text).map_err(|e| format!("{}: {}", filename, e))?;
+        skin.sheet = format!("{}/{}", dir, skin.sheet);
+        Ok(skin)
+    }
+
+    pub fn parse(name: &str, text: &str) -> Result<Skin, String> {
+        let mut sheet = None;
+        let mut tile_size = None;
+        let mut tiles = HashMap::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let key = words.next().unwrap();
+            let values: Vec<&str> = words.collect();
+            match key {
+                "sheet" => sheet = values.first().map(|s| s.to_string()),
+                "tile" => {
+                    tile_size = values
+                        .first()
+                        .and_then(|v| v.parse().ok())
+                        .filter(|&size: &u32| size > 0)
+                }
+                "block" | "cannon" | "bullet" | "erase" => {
+                    let numbers: Vec<u32> = values
+                        .iter()
+                        .map(|v| v.parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| format!("invalid position: {}", line))?;
+                    if numbers.is_empty() || !numbers.len().is_multiple_of(2) {
+                        return Err(format!("invalid position: {}", line));
+                    }
+                    let positions = numbers.chunks(2).map(|p| (p[0], p[1])).collect();
+                    tiles.insert(key, positions);
+                }
+                _ => return Err(format!("unknown key: {}", key)),
+            }
+        }
+
+        let mut tile = |key| -> Result<Vec<(u32, u32)>, String> {
+            tiles.remove(key).ok_or(format!("{} is not given", key))
+        };
+        Ok(Skin {
+            name: name.to_string(),
+            sheet: sheet.ok_or("sheet is not given")?,
+            tile_size: tile_size.ok_or("tile is not given or invalid")?,
+            block: tile("block")?[0],
+            cannon: tile("cannon")?[0],
+            bullet: tile("bullet")?[0],
+            erase: tile("erase")?,
+        })
+    }
+
+    fn tile(&self, (x, y): (u32, u32)) -> Tile {
+        let size = self.tile_size;
+        ((x * size) as i32, (y * size) as i32, size, size)
+    }
+
+    // 別のブロックと接する辺に合わせたタイル
+    pub fn block_tile(&self, borders: Borders) -> Tile {
+        let mut index = 0;
+        for (border, bit) in [
+            (borders.top, TOP),
+            (borders.right, RIGHT),
+            (borders.bottom, BOTTOM),
+            (borders.left, LEFT),
+        ] {
+            if border {
+                index |= bit;
+            }
+        }
+        self.tile((self.block.0 + index as u32, self.block.1))
+    }
+
+    pub fn cannon_tile(&self) -> Tile {
+        self.tile(self.cannon)
+    }
+
+    pub fn bullet_tile(&self) -> Tile {
+        self.tile(self.bullet)
+    }
+
+    // ゲームのフレーム数に合わせて2フレームごとにコマを進める
+    pub fn erase_tile(&self, frame: i32) -> Tile {
+        let i = (frame.max(0) as usize / 2) % self.erase.len();
+        self.tile(self.erase[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_skin() {
+        let skin = Skin::load("bevel").unwrap();
+        assert_eq!(skin.sheet, "resources/skin/bevel/sheet.png");
+        assert_eq!(skin.block_tile(Borders::default()), (0, 0, 30, 30));
+        let borders = Borders {
+            top: true,
+            left: true,
+            ..Borders::default()
+        };
+        assert_eq!(skin.block_tile(borders), (9 * 30, 0, 30, 30));
+        assert_eq!(skin.cannon_tile(), (0, 30, 30, 30));
+        assert_eq!(skin.erase_tile(0), skin.erase_tile(1));
+        assert_eq!(skin.erase_tile(2), (3 * 30, 30, 30, 30));
+        assert_eq!(skin.erase_tile(8), skin.erase_tile(0));
+
+        // スプライトシートはすべてのタイルを含む大きさ
+        let sheet = crate::image::RgbaImage::load_png(&skin.sheet).unwrap();
+        assert_eq!((sheet.width, sheet.height), (16 * 30, 2 * 30));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let text = "sheet a.bmp\ntile 16\nblock 0 0\ncannon 0 1\nbullet 1 1\n";
+        assert_eq!(
+            Skin::parse("x", text),
+            Err("erase is not given".to_string())
+        );
+        assert!(Skin::parse("x", &format!("{}erase 2\n", text)).is_err());
+        assert!(Skin::parse("x", &format!("{}erase 2 1\nshadow 0 0\n", text)).is_err());
+        assert!(Skin::parse("x", &format!("{}erase 2 1\n", text)).is_ok());
+    }
+}
@@ -0,0 +1,471 @@
+use serde_json::json;
+use std::fs::File;
+use std::io::Write;
+use std::net::TcpListener;
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::bot::field_rows;
+use crate::field::*;
+use crate::model::*;
+
+// 書き込みスレッドに受け渡していない観戦者
+type Clients = Arc<Mutex<Vec<Box<dyn Write + Send>>>>;
+
+// 書き込みスレッドに溜められるフレーム数。溢れたフレームは捨ててフィールド全体を送り直す
+const QUEUE_SIZE: usize = 60;
+
+// この時間書き込めない観戦者は遅れているとみなして切断する
+const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+// 新しい観戦者を待つ間隔
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+// 観戦者に見せている状態。途中から来た観戦者にはこれを最初に送る
+#[derive(Clone)]
+struct Snapshot {
+    frame: i32,
+    field: Field,
+    score: i32,
+    progress: i32,
+    is_over: bool,
+    is_clear: bool,
+}
+
+impl Snapshot {
+    fn messages(&self) -> Vec<String> {
+        vec![
+            field_message(self.frame, &self.field),
+            score_message(self.frame, self.score, self.progress),
+            status_message(self.frame, self.is_over, self.is_clear),
+        ]
+    }
+}
+
+// 1フレーム分のメッセージと、それを送り終えた後の状態
+struct Update {
+    messages: Vec<String>,
+    snapshot: Snapshot,
+}
+
+// 観戦用にGameの状態の変化を1行1メッセージのJSONで書き出す
+// 書き込みは別スレッドで行うので、ゲームループのフレーム時間には影響しない
+//
+// {"type":"field","frame":0,"field":["...",...]}           フィールド全体
+// {"type":"diff","frame":1,"cells":[{"x":0,"y":0,"cell":"a"}]}  変化したセル
+// {"type":"score","frame":1,"score":0,"progress":0}
+// {"type":"erase","frame":1,"rectangle":{...},"block_count":3,"score":360}
+// {"type":"status","frame":1,"is_over":true,"is_clear":false}
+pub struct Spectator {
+    sender: Option<SyncSender<Update>>,
+    writer: Option<JoinHandle<()>>,
+    needs_snapshot: bool, // 送れなかったフレームがあるのでフィールド全体を送る
+    messages: Vec<String>,
+    frame: i32,
+    field: Field,
+    score: i32,
+    progress: i32,
+    status: Option<(bool, bool)>, // 最後に送った(is_over, is_clear)。Noneなら次のフレームで送る
+}
+
+impl Spectator {
+    // targetが"unix:"で始まる場合はUnixドメインソケット、host:portならTCPで待ち受け、
+    // それ以外はファイルに書き出す
+    pub fn open(target: &str) -> Result<Spectator, String> {
+        let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+
+        if let Some(path) = target.strip_prefix("unix:") {
+            listen_unix(path, clients.clone())?;
+        } else if is_host_port(target) {
+            // 名前を解決できなければファイルにはせずエラーにする
+            let listener = TcpListener::bind(target).map_err(|e| format!("{}: {}", target, e))?;
+            let clients = clients.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    stream.set_write_timeout(Some(WRITE_TIMEOUT)).ok();
+                    clients.lock().unwrap().push(Box::new(stream));
+                }
+            });
+        } else {
+            let file = File::create(target).map_err(|e| format!("{}: {}", target, e))?;
+            clients.lock().unwrap().push(Box::new(file));
+        }
+
+        Ok(Spectator::new(clients))
+    }
+
+    fn new(new_clients: Clients) -> Spectator {
+        let (sender, receiver) = mpsc::sync_channel::<Update>(QUEUE_SIZE);
+        let writer = thread::spawn(move || {
+            let mut clients: Vec<Box<dyn Write + Send>> = Vec::new();
+            let mut snapshot: Option<Snapshot> = None;
+            loop {
+                let update = match receiver.recv_timeout(ACCEPT_INTERVAL) {
+                    Ok(update) => Some(update),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+
+                // 新しい観戦者には、これまでに送った差分を当てた後の状態を先に送る
+                for mut client in new_clients.lock().unwrap().drain(..) {
+                    let messages = snapshot.as_ref().map_or(Vec::new(), |s| s.messages());
+                    if write_messages(&mut client, &messages) {
+                        clients.push(client);
+                    }
+                }
+
+                if let Some(update) = update {
+                    // 書き込めなくなった観戦者は外す
+                    clients.retain_mut(|c| write_messages(c, &update.messages));
+                    snapshot = Some(update.snapshot);
+                }
+            }
+        });
+
+        Spectator {
+            sender: Some(sender),
+            writer: Some(writer),
+            needs_snapshot: true,
+            messages: Vec::new(),
+            frame: 0,
+            field: Field::new(),
+            score: 0,
+            progress: 0,
+            status: None,
+        }
+    }
+
+    // 前回からの変化を送る。eventsはこのフレームでGameから取り出したもの
+    pub fn publish(&mut self, game: &Game, events: &[GameEvent]) {
+        let frame = game.frame;
+
+        if self.needs_snapshot || game.frame == 0 {
+            self.messages.push(field_message(frame, &game.field));
+            self.field = game.field.clone();
+            // 送れなかったフレームに得点や終了の知らせがあったかもしれないので、全部送り直す
+            self.score = -1;
+            self.status = None;
+            self.needs_snapshot = false;
+        }
+
+        let mut cells = Vec::new();
+        for y in 0..FIELD_H {
+            for x in 0..FIELD_W {
+                if self.field.cells[y][x] != game.field.cells[y][x] {
+                    cells.push(json!({"x": x, "y": y, "cell": game.field.cells[y][x].to_string()}));
+                }
+            }
+        }
+        if !cells.is_empty() {
+            self.send(json!({"type": "diff", "frame": frame, "cells": cells}));
+            self.field = game.field.clone();
+        }
+
+        let progress = game.get_progress();
+        if self.score != game.score || self.progress != progress {
+            self.messages
+                .push(score_message(frame, game.score, progress));
+            self.score = game.score;
+            self.progress = progress;
+        }
+
+        for event in events {
+            if let GameEvent::Erased {
+                rectangle,
+                block_count,
+                score,
+                player,
+            } = event
+            {
+                self.send(json!({
+                    "type": "erase",
+                    "frame": frame,
+                    "rectangle": {
+                        "left": rectangle.left,
+                        "top": rectangle.top,
+                        "right": rectangle.right,
+                        "bottom": rectangle.bottom,
+                    },
+                    "block_count": block_count,
+                    "score": score,
+                    "player": player,
+                }));
+            }
+        }
+
+        let status = (game.is_over, game.is_clear);
+        if self.status != Some(status) {
+            self.messages
+                .push(status_message(frame, game.is_over, game.is_clear));
+            self.status = Some(status);
+        }
+
+        self.flush(frame);
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            frame: self.frame,
+            field: self.field.clone(),
+            score: self.score,
+            progress: self.progress,
+            is_over: self.status.is_some_and(|(is_over, _)| is_over),
+            is_clear: self.status.is_some_and(|(_, is_clear)| is_clear),
+        }
+    }
+
+    fn send(&mut self, message: serde_json::Value) {
+        self.messages.push(message.to_string());
+    }
+
+    // このフレームのメッセージを書き込みスレッドに渡す
+    // 書き込みが追いつかずに溢れたら捨てて、次のフレームでフィールド全体を送り直す
+    fn flush(&mut self, frame: i32) {
+        self.frame = frame;
+        let update = Update {
+            messages: std::mem::take(&mut self.messages),
+            snapshot: self.snapshot(),
+        };
+        if let Some(sender) = &self.sender {
+            if let Err(TrySendError::Full(_)) = sender.try_send(update) {
+                self.needs_snapshot = true;
+            }
+        }
+    }
+}
+
+impl Drop for Spectator {
+    // 送り残したメッセージを書き出してから終わる
+    fn drop(&mut self) {
+        // 溢れて送れなかったフレームがあれば、最後の状態を待ってでも送る
+        if let (true, Some(sender)) = (self.needs_snapshot, &self.sender) {
+            let snapshot = self.snapshot();
+            let messages = snapshot.messages();
+            sender.send(Update { messages, snapshot }).ok();
+        }
+        self.sender = None;
+        if let Some(writer) = self.writer.take() {
+            writer.join().ok();
+        }
+    }
+}
+
+// "localhost:9000"や"127.0.0.1:9000"のようにポート番号で終わるか
+fn is_host_port(target: &str) -> bool {
+    target
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+}
+
+fn field_message(frame: i32, field: &Field) -> String {
+    json!({"type": "field", "frame": frame, "field": field_rows(field)}).to_string()
+}
+
+fn score_message(frame: i32, score: i32, progress: i32) -> String {
+    json!({"type": "score", "frame": frame, "score": score, "progress": progress}).to_string()
+}
+
+fn status_message(frame: i32, is_over: bool, is_clear: bool) -> String {
+    json!({"type": "status", "frame": frame, "is_over": is_over, "is_clear": is_clear}).to_string()
+}
+
+// 書き込めなければfalse
+fn write_messages(client: &mut Box<dyn Write + Send>, messages: &[String]) -> bool {
+    messages
+        .iter()
+        .try_for_each(|line| writeln!(client, "{}", line))
+        .and_then(|_| client.flush())
+        .is_ok()
+}
+
+#[cfg(unix)]
+fn listen_unix(path: &str, clients: Clients) -> Result<(), String> {
+    use std::os::unix::net::UnixListener;
+
+    std::fs::remove_file(path).ok();
+    let listener = UnixListener::bind(path).map_err(|e| e.to_string())?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            stream.set_write_timeout(Some(WRITE_TIMEOUT)).ok();
+            clients.lock().unwrap().push(Box::new(stream));
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn listen_unix(_path: &str, _clients: Clients) -> Result<(), String> {
+    Err("unix sockets are not supported on this platform".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // 書き込まれた内容を後から読めるWrite
+    // blockedの間は書き込みを待たせて、遅い観戦者の代わりにする
+    #[derive(Clone, Default)]
+    struct SharedBuffer {
+        data: Arc<Mutex<Vec<u8>>>,
+        blocked: Arc<AtomicBool>,
+        waiting: Arc<AtomicBool>, // 書き込みスレッドが待たされている
+    }
+
+    impl SharedBuffer {
+        fn messages(&self) -> Vec<serde_json::Value> {
+            parse_messages(&String::from_utf8(self.data.lock().unwrap().clone()).unwrap())
+        }
+
+        fn set_blocked(&self, blocked: bool) {
+            self.blocked.store(blocked, Ordering::SeqCst);
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            while self.blocked.load(Ordering::SeqCst) {
+                self.waiting.store(true, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(1));
+            }
+            self.data.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn parse_messages(content: &str) -> Vec<serde_json::Value> {
+        content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    // フィールド全体と差分を順に当てる
+    fn apply_messages(messages: &[serde_json::Value]) -> Vec<String> {
+        let mut rows: Vec<Vec<char>> = Vec::new();
+        for message in messages {
+            if message["type"] == "field" {
+                rows = message["field"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|row| row.as_str().unwrap().chars().collect())
+                    .collect();
+            } else if message["type"] == "diff" {
+                for cell in message["cells"].as_array().unwrap() {
+                    let x = cell["x"].as_u64().unwrap() as usize;
+                    let y = cell["y"].as_u64().unwrap() as usize;
+                    rows[y][x] = cell["cell"].as_str().unwrap().chars().next().unwrap();
+                }
+            }
+        }
+        rows.into_iter()
+            .map(|row| row.into_iter().collect())
+            .collect()
+    }
+
+    fn play(spectator: &mut Spectator, game: &mut Game, frames: usize) {
+        for _ in 0..frames {
+            game.update(Command::None);
+            let events = std::mem::take(&mut game.events);
+            spectator.publish(game, &events);
+        }
+    }
+
+    #[test]
+    fn test_stream_to_file() {
+        let path = std::env::temp_dir().join(format!("spectator-{}.jsonl", std::process::id()));
+        let mut spectator = Spectator::open(path.to_str().unwrap()).unwrap();
+
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.load_stage("resources/data/stage1.dat");
+        play(&mut spectator, &mut game, 30);
+        drop(spectator);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let messages = parse_messages(&content);
+        assert_eq!(messages[0]["type"], "field");
+        assert_eq!(messages[0]["frame"], 0);
+        assert_eq!(messages[1]["type"], "score");
+
+        // 差分を順に当てるとフィールドが再現できる
+        assert_ne!(game.field, Field::new());
+        assert_eq!(apply_messages(&messages), field_rows(&game.field));
+    }
+
+    #[test]
+    fn test_late_client_gets_snapshot_first() {
+        let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+        let mut spectator = Spectator::new(clients.clone());
+
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.load_stage("resources/data/stage1.dat");
+        play(&mut spectator, &mut game, 10);
+
+        // 途中から来た観戦者には、溜まっている差分より先にフィールド全体が届く
+        let buffer = SharedBuffer::default();
+        clients.lock().unwrap().push(Box::new(buffer.clone()));
+        play(&mut spectator, &mut game, 60);
+        drop(spectator);
+
+        let messages = buffer.messages();
+        assert_eq!(messages[0]["type"], "field");
+        assert_eq!(apply_messages(&messages), field_rows(&game.field));
+    }
+
+    #[test]
+    fn test_status_is_resent_after_overflow() {
+        let buffer = SharedBuffer::default();
+        let clients: Clients = Arc::new(Mutex::new(vec![Box::new(buffer.clone())]));
+        let mut spectator = Spectator::new(clients);
+
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.load_stage("resources/data/stage1.dat");
+
+        // 書き込みスレッドを止めてから、キューが溢れるまで進める
+        buffer.set_blocked(true);
+        play(&mut spectator, &mut game, 1);
+        while !buffer.waiting.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        while !spectator.needs_snapshot {
+            play(&mut spectator, &mut game, 1);
+        }
+
+        // ゲームオーバーになったフレームは溢れて捨てられる
+        game.field.cells[FIELD_H - 1][0] = 'z';
+        play(&mut spectator, &mut game, 1);
+        assert!(game.is_over);
+        assert!(spectator.needs_snapshot);
+
+        // 書き込めるようになったら、送り直すときに終了も知らせる
+        buffer.set_blocked(false);
+        while spectator.needs_snapshot {
+            thread::sleep(Duration::from_millis(1));
+            play(&mut spectator, &mut game, 1);
+        }
+        drop(spectator);
+
+        let messages = buffer.messages();
+        let status = messages.iter().rev().find(|m| m["type"] == "status");
+        assert_eq!(status.unwrap()["is_over"], true);
+        assert_eq!(apply_messages(&messages), field_rows(&game.field));
+    }
+
+    #[test]
+    fn test_host_port() {
+        assert!(is_host_port("localhost:9000"));
+        assert!(is_host_port("127.0.0.1:9000"));
+        assert!(is_host_port("[::1]:9000"));
+        assert!(!is_host_port("spectate.jsonl"));
+        assert!(!is_host_port(":9000"));
+        assert!(!is_host_port("C:\\spectate.jsonl"));
+    }
+}
@@ -0,0 +1,293 @@
+use std::io::{Read, Write};
+use std::process::{Command as Process, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::field::*;
+use crate::model::*;
+use crate::palette::*;
+use crate::view::*;
+
+// ANSIエスケープシーケンスで端末に描画する。1マスを全角2文字分で表す
+pub struct TerminalRenderer<W: Write> {
+    out: W,
+    colors: BlockColors,
+}
+
+impl<W: Write> TerminalRenderer<W> {
+    pub fn new(out: W, colors: BlockColors) -> TerminalRenderer<W> {
+        TerminalRenderer { out, colors }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> Renderer for TerminalRenderer<W> {
+    fn render(&mut self, view: &GameView) -> Result<(), String> {
+        self.colors.assign(view.field);
+        let mut screen = String::from("\x1b[H");
+        for (y, line) in render_lines(view, &self.colors).iter().enumerate() {
+            screen += line;
+            screen += "\x1b[0m\x1b[K";
+            if let Some(info) = info_line(view, y) {
+                screen += "  ";
+                screen += &info;
+            }
+            // rawモードでは改行だけでは行頭に戻らない
+            screen += "\r\n";
+        }
+        self.out
+            .write_all(screen.as_bytes())
+            .and_then(|_| self.out.flush())
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn background((r, g, b): (u8, u8, u8)) -> String {
+    format!("\x1b[48;2;{};{};{}m", r, g, b)
+}
+
+// フィールドと砲台の行。最後の行は砲台
+fn render_lines(view: &GameView, colors: &BlockColors) -> Vec<String> {
+    let mut cells: Vec<Vec<String>> = (0..FIELD_H)
+        .map(|y| {
+            (0..FIELD_W)
+                .map(|x| match view.field.cells[y][x] {
+                    EMPTY => "\x1b[0m  ".to_string(),
+                    ERASING => background((255, 255, 255)) + "  ",
+                    _ => {
+                        let color = background(colors.color(x, y).unwrap());
+                        match colors.glyph(x, y) {
+                            Some(glyph) => format!("{}\x1b[30m{} ", color, glyph),
+                            None => color + "  ",
+                        }
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    for sight in view.sights.iter().flatten() {
+        cells[sight.y][sight.x] = "\x1b[0m[]".to_string();
+    }
+    for bullet in view.bullets {
+        let (r, g, b) = player_color(bullet.owner);
+        cells[bullet.pos.y][bullet.pos.x] = background((r / 3 * 2, g / 3 * 2, b / 3 * 2)) + "  ";
+    }
+    if let Some(ghost) = &view.ghost {
+        for bullet in ghost.bullets {
+            cells[bullet.pos.y][bullet.pos.x] = "\x1b[0m::".to_string();
+        }
+    }
+
+    let mut players = vec!["\x1b[0m  ".to_string(); FIELD_W];
+    if let Some(ghost) = &view.ghost {
+        for player in ghost.players {
+            players[player.x] = "\x1b[0m::".to_string();
+        }
+    }
+    for (i, player) in view.players.iter().enumerate() {
+        players[player.x] = background(player_color(i)) + "  ";
+    }
+
+    let mut lines: Vec<String> = cells.into_iter().map(|row| row.concat()).collect();
+    lines.push(players.concat());
+    lines
+}
+
+// フィールドの右に出す情報
+fn info_line(view: &GameView, y: usize) -> Option<String> {
+    match y {
+        1 => Some(format!("{:3} pct", view.progress)),
+        2 => Some(format!("SCORE {:05}", view.score)),
+        4 if view.is_over => Some("GAME OVER".to_string()),
+        4 if view.is_clear => Some("CONGRATULATIONS!".to_string()),
+        6 => view.erased_texts.last().map(|text| text.text.clone()),
+        8 => view
+            .ghost
+            .as_ref()
+            .map(|ghost| format!("GHOST {:+06}", ghost.score_delta)),
+        10 if view.is_debug => Some(format!("frame {}", view.frame)),
+        _ => {
+            let i = y.checked_sub(12)?;
+            if view.players.len() > 1 && i < view.players.len() {
+                Some(format!("{}P {:05}", i + 1, view.players[i].score))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+// 端末から読んだキー
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TerminalKey {
+    Command(Command),
+    Restart,
+    Quit,
+}
+
+// 矢印キーかWASD、Spaceで操作する
+pub fn parse_keys(bytes: &[u8]) -> Vec<TerminalKey> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let key = match bytes[i..] {
+            [0x1b, b'[', code, ..] => {
+                i += 2;
+                match code {
+                    b'A' => Some(TerminalKey::Command(Command::Up)),
+                    b'C' => Some(TerminalKey::Command(Command::Right)),
+                    b'D' => Some(TerminalKey::Command(Command::Left)),
+                    b'B' => Some(TerminalKey::Command(Command::Shoot)),
+                    _ => None,
+                }
+            }
+            [b'a' | b'h', ..] => Some(TerminalKey::Command(Command::Left)),
+            [b'd' | b'l', ..] => Some(TerminalKey::Command(Command::Right)),
+            [b'w' | b'k', ..] => Some(TerminalKey::Command(Command::Up)),
+            [b' ' | b's' | b'j', ..] => Some(TerminalKey::Command(Command::Shoot)),
+            [b'\r' | b'\n', ..] => Some(TerminalKey::Restart),
+            [b'q' | 0x03, ..] => Some(TerminalKey::Quit),
+            _ => None,
+        };
+        keys.extend(key);
+        i += 1;
+    }
+    keys
+}
+
+// 端末をrawモードにして、キー入力を別スレッドで読む
+// 終わるときに端末の設定を元に戻す
+pub struct TerminalInput {
+    saved_mode: String,
+    bytes: Receiver<Vec<u8>>,
+}
+
+impl TerminalInput {
+    pub fn start() -> Result<TerminalInput, String> {
+        let saved_mode = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        print!("\x1b[2J\x1b[?25l");
+
+        let (sender, bytes) = mpsc::channel();
+        thread::spawn(move || {
+            let mut stdin = std::io::stdin();
+            let mut buf = [0; 64];
+            while let Ok(n) = stdin.read(&mut buf) {
+                if n == 0 || sender.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(TerminalInput {
+            saved_mode: saved_mode.trim().to_string(),
+            bytes,
+        })
+    }
+
+    // 前のフレーム以降に押されたキー
+    pub fn keys(&mut self) -> Vec<TerminalKey> {
+        let bytes: Vec<u8> = self.bytes.try_iter().flatten().collect();
+        parse_keys(&bytes)
+    }
+}
+
+impl Drop for TerminalInput {
+    fn drop(&mut self) {
+        stty(&[&self.saved_mode]).ok();
+        print!("\x1b[0m\x1b[?25h\r\n");
+        std::io::stdout().flush().ok();
+    }
+}
+
+fn stty(args: &[&str]) -> Result<String, String> {
+    let output = Process::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("stty: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "stty: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::*;
+
+    // エスケープシーケンスを取り除く
+    fn strip_ansi(text: &str) -> String {
+        let mut result = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            } else {
+                result.push(c);
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_render_frame() {
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.load_stage("resources/data/stage1.dat");
+        game.score = 1234;
+        for _ in 0..3 {
+            game.update(Command::Shoot);
+        }
+        game.field.cells[0][0] = 'a';
+
+        let palette = Palette::load("default").unwrap();
+        let mut renderer = TerminalRenderer::new(Vec::new(), BlockColors::new(palette.clone()));
+        renderer.render(&GameView::new(&game)).unwrap();
+        let output = String::from_utf8(renderer.into_inner()).unwrap();
+
+        assert!(output.starts_with("\x1b[H"));
+        let lines: Vec<String> = output.split("\r\n").map(strip_ansi).collect();
+        assert_eq!(lines.len(), FIELD_H + 2);
+        assert!(lines[2].ends_with("SCORE 01234"));
+        assert_eq!(lines[0].chars().count(), FIELD_W * 2);
+        assert!(output.contains(&background(player_color(0))));
+        let a = palette.colors['a' as usize % palette.colors.len()];
+        assert!(output.contains(&background(a)));
+
+        let mut colors = BlockColors::new(palette);
+        colors.glyphs = true;
+        renderer = TerminalRenderer::new(Vec::new(), colors);
+        renderer.render(&GameView::new(&game)).unwrap();
+        let output = strip_ansi(&String::from_utf8(renderer.into_inner()).unwrap());
+        assert!(output.starts_with(&format!("{} ", GLYPHS['a' as usize % 6])));
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(
+            parse_keys(b"\x1b[D\x1b[Ca \x1b[Aq\r"),
+            vec![
+                TerminalKey::Command(Command::Left),
+                TerminalKey::Command(Command::Right),
+                TerminalKey::Command(Command::Left),
+                TerminalKey::Command(Command::Shoot),
+                TerminalKey::Command(Command::Up),
+                TerminalKey::Quit,
+                TerminalKey::Restart,
+            ]
+        );
+        assert_eq!(parse_keys(b"\x1b[Zx"), vec![]);
+    }
+}
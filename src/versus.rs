@@ -0,0 +1,144 @@
+use crate::field::*;
+use crate::model::*;
+
+// 2つのGameを並べて対戦させる
+// 大きな矩形を消すと相手のフィールドの上から行を押し込む
+#[derive(Debug, Clone)]
+pub struct Versus {
+    pub games: [Game; 2],
+    pub is_finished: bool,
+    pub winner: Option<usize>, // 両者同時にゲームオーバーになった場合はNone
+}
+
+// 消した矩形の大きさとブロックの種類数に応じて相手に送る行数
+pub fn attack_rows(rectangle: &Rectangle, block_count: i32) -> i32 {
+    rectangle.area() as i32 / 20 + (block_count - 3).max(0)
+}
+
+impl Versus {
+    pub fn new(games: [Game; 2]) -> Versus {
+        Versus {
+            games,
+            is_finished: false,
+            winner: None,
+        }
+    }
+
+    pub fn update(&mut self, commands: [Command; 2]) {
+        if self.is_finished {
+            return;
+        }
+
+        let mut attacks = [0; 2];
+        for (i, game) in self.games.iter_mut().enumerate() {
+            // eventsはフロントエンドが取り出すので、このフレームで増えた分だけ見る
+            let start = game.events.len();
+            game.update(commands[i]);
+            for event in &game.events[start..] {
+                if let GameEvent::Erased {
+                    rectangle,
+                    block_count,
+                    ..
+                } = event
+                {
+                    attacks[i] += attack_rows(rectangle, *block_count);
+                }
+            }
+        }
+        self.games[0].garbage_rows += attacks[1];
+        self.games[1].garbage_rows += attacks[0];
+
+        let is_over = [self.games[0].is_over, self.games[1].is_over];
+        let is_clear = [self.games[0].is_clear, self.games[1].is_clear];
+        if is_over[0] || is_over[1] {
+            self.is_finished = true;
+            self.winner = match is_over {
+                [false, true] => Some(0),
+                [true, false] => Some(1),
+                _ => None,
+            };
+        } else if is_clear[0] || is_clear[1] {
+            // 先にステージを最後までクリアした方の勝ち
+            self.is_finished = true;
+            self.winner = match is_clear {
+                [true, false] => Some(0),
+                [false, true] => Some(1),
+                _ => None,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::*;
+
+    fn new_game() -> Game {
+        let mut game = Game::with_seed(0, Box::new(NullRecorder));
+        game.load_stage("resources/data/stage1.dat");
+        game
+    }
+
+    #[test]
+    fn test_attack_rows() {
+        let small = Rectangle {
+            left: 0,
+            top: 0,
+            right: 3,
+            bottom: 2,
+        };
+        let large = Rectangle {
+            left: 3,
+            top: 3,
+            right: 9,
+            bottom: 10,
+        };
+        assert_eq!(attack_rows(&small, 2), 0);
+        assert_eq!(attack_rows(&large, 2), 2);
+        assert_eq!(attack_rows(&large, 7), 6);
+    }
+
+    #[test]
+    fn test_garbage_rows_push_opponent_down() {
+        let mut versus = Versus::new([new_game(), new_game()]);
+        for _ in 0..40 {
+            versus.update([Command::None, Command::None]);
+        }
+        let next_row = versus.games[1].next_row;
+        let field = versus.games[1].field.clone();
+
+        versus.games[1].garbage_rows = 3;
+        for _ in 0..3 {
+            versus.update([Command::None, Command::None]);
+        }
+        // ステージは進まず、フィールドが3行下がって上に送られた行が入る
+        assert_eq!(versus.games[1].garbage_rows, 0);
+        assert_eq!(versus.games[1].next_row, next_row);
+        assert_eq!(versus.games[1].field.cells[3..], field.cells[..FIELD_H - 3]);
+        assert_eq!(versus.games[1].field.cells[0], [GARBAGE[0]; FIELD_W]);
+        assert_eq!(versus.games[1].field.cells[1], [GARBAGE[1]; FIELD_W]);
+        assert_eq!(versus.games[1].field.cells[2], [GARBAGE[0]; FIELD_W]);
+    }
+
+    #[test]
+    fn test_attacked_player_loses() {
+        let mut versus = Versus::new([new_game(), new_game()]);
+        while !versus.is_finished {
+            versus.games[1].garbage_rows += 1;
+            versus.update([Command::None, Command::None]);
+        }
+        assert!(versus.games[1].is_over);
+        assert!(!versus.games[1].is_clear);
+        assert_eq!(versus.winner, Some(0));
+    }
+
+    #[test]
+    fn test_winner_is_decided_by_gameover() {
+        let mut versus = Versus::new([new_game(), new_game()]);
+        versus.games[1].field.cells[FIELD_H - 1][0] = 'z';
+        versus.update([Command::None, Command::None]);
+        assert!(versus.is_finished);
+        assert_eq!(versus.winner, Some(0));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::field::*;
+use crate::ghost::*;
+use crate::model::*;
+
+// 描画に必要なGameの状態を読み取り専用でまとめたもの
+pub struct GameView<'a> {
+    pub field: &'a Field,
+    pub sights: Vec<Option<Point>>, // プレイヤーごとの照準
+    pub players: &'a [Player],
+    pub bullets: &'a [Bullet],
+    pub erased_texts: &'a [ErasedText],
+    pub scroll_offset: f32, // 次のスクロールまでの進み具合。フィールドをこれだけ下げて描く
+    pub incoming_row: Option<[Cell; FIELD_W]>, // 次のスクロールで上から入ってくる行
+    pub progress: i32,
+    pub score: i32,
+    pub frame: i32,
+    pub is_over: bool,
+    pub is_clear: bool,
+    pub is_debug: bool,
+    pub ghost: Option<GhostView<'a>>,
+}
+
+// 今のゲームに重ねて描くゴースト
+pub struct GhostView<'a> {
+    pub players: &'a [Player],
+    pub bullets: &'a [Bullet],
+    pub score_delta: i32, // 今のゲームがゴーストより何点上回っているか
+}
+
+impl<'a> GameView<'a> {
+    pub fn new(game: &'a Game) -> GameView<'a> {
+        GameView {
+            field: &game.field,
+            sights: (0..game.players.len())
+                .map(|i| game.get_sight_pos(i))
+                .collect(),
+            players: &game.players,
+            bullets: &game.bullets,
+            erased_texts: &game.erased_texts,
+            scroll_offset: game.scroll_offset(),
+            incoming_row: game.incoming_row(),
+            progress: game.get_progress(),
+            score: game.score,
+            frame: game.frame,
+            is_over: game.is_over,
+            is_clear: game.is_clear,
+            is_debug: game.is_debug,
+            ghost: None,
+        }
+    }
+
+    pub fn with_ghost(self, ghost: &'a Ghost, game: &Game) -> GameView<'a> {
+        GameView {
+            ghost: Some(GhostView {
+                players: &ghost.game.players,
+                bullets: &ghost.game.bullets,
+                score_delta: ghost.score_delta(game),
+            }),
+            ..self
+        }
+    }
+}
+
+// 画面への描画方法。SDLのウィンドウや端末など
+pub trait Renderer {
+    fn render(&mut self, view: &GameView) -> Result<(), String>;
+}
+
+pub fn player_color(i: usize) -> (u8, u8, u8) {
+    match i {
+        0 => (192, 192, 192),
+        _ => (255, 192, 96),
+    }
+}
+
+// 移動中の砲台の、マスの位置からのずれ（1マスを1.0とする）
+pub fn player_offset(player: &Player) -> f32 {
+    if player.move_wait > 0 {
+        let dir = if player.move_dir == Direction::Left {
+            -1.0
+        } else {
+            1.0
+        };
+        dir * ((MOVE_WAIT - player.move_wait) as f32 / MOVE_WAIT as f32)
+    } else {
+        0.0
+    }
+}
@@ -0,0 +1,83 @@
+// tests/replays/*.datを再生し、結果を同じ名前の.expectedと比べる
+// 意図してゲームの挙動を変えたときは BLESS=1 cargo test --test golden_replays で書き直す
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rust_quarth::bot::field_rows;
+use rust_quarth::replay::*;
+
+const REPLAY_DIR: &str = "tests/replays";
+const STAGE: &str = "resources/data/stage1.dat";
+
+// ゲームオーバーかクリアになるか、コマンドが尽きるまで進めた結果
+fn run(replay_path: &Path) -> String {
+    let replay = Replay::load(replay_path.to_str().unwrap()).unwrap();
+    let mut game = replay.new_game(STAGE).unwrap();
+    for commands in &replay.commands {
+        if game.is_over || game.is_clear {
+            break;
+        }
+        game.update_players(commands);
+    }
+
+    let mut lines = vec![
+        format!("frame {}", game.frame),
+        format!("score {}", game.score),
+        format!("is_over {}", game.is_over),
+        format!("is_clear {}", game.is_clear),
+        "field".to_string(),
+    ];
+    lines.extend(
+        field_rows(&game.field)
+            .into_iter()
+            .map(|row| format!("|{}|", row)),
+    );
+    lines.join("\n") + "\n"
+}
+
+fn replay_paths() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(REPLAY_DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "dat"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn golden_replays() {
+    let bless = std::env::var_os("BLESS").is_some();
+    let paths = replay_paths();
+    assert!(!paths.is_empty(), "no replays in {}", REPLAY_DIR);
+
+    let mut failures = Vec::new();
+    for path in paths {
+        let actual = run(&path);
+        let expected_path = path.with_extension("expected");
+        if bless {
+            fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+
+        let Ok(expected) = fs::read_to_string(&expected_path) else {
+            failures.push(format!("{}: missing", expected_path.display()));
+            continue;
+        };
+        if actual != expected {
+            let diff: Vec<String> = expected
+                .lines()
+                .zip(actual.lines())
+                .filter(|(e, a)| e != a)
+                .map(|(e, a)| format!("  expected {}\n  actual   {}", e, a))
+                .collect();
+            failures.push(format!("{}:\n{}", path.display(), diff.join("\n")));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "golden replays differ (run with BLESS=1 to update):\n{}",
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,113 @@
+// 弾とスクロール、消去エフェクトの組み合わせの挙動を台本で確かめる
+use rust_quarth::erase::*;
+use rust_quarth::scenario::*;
+
+#[test]
+fn bullet_fills_corner_and_erases_rectangle() {
+    #[rustfmt::skip]
+    let field = concat!(
+        "          aa\n",
+        "           a\n",
+    );
+    let mut scenario = Scenario::new(field, "\n\n\n\n");
+    scenario.set_players(&[10]);
+
+    scenario.run("Shoot None*15");
+    #[rustfmt::skip]
+    let erasing = concat!(
+        "          **\n",
+        "          **\n",
+    );
+    scenario.assert_field(erasing);
+
+    scenario.run("None*8");
+    scenario.assert_field("");
+    assert_eq!(scenario.game.score, 40);
+}
+
+#[test]
+fn bullet_hits_row_scrolled_in_from_stage() {
+    // スクロールがなければ弾は画面の上に抜けていく
+    let mut scenario = Scenario::new("z\n", "   d\n");
+    scenario.set_players(&[3]);
+    scenario.game.scroll_wait = 10;
+
+    scenario.run("Shoot None*13");
+    scenario.assert_field("   d\nz\n");
+    assert_eq!(scenario.game.bullets[0].pos.y, 3);
+
+    scenario.run("None*5");
+    scenario.assert_field("   d\nz  d\n");
+    assert!(scenario.game.bullets.is_empty());
+}
+
+#[test]
+fn bullet_catches_block_moved_by_scroll() {
+    // 弾が届く直前にスクロールしたブロックには、1段下で当たる
+    let mut scenario = Scenario::new("   c\n", "\n\n");
+    scenario.set_players(&[3]);
+    scenario.game.scroll_wait = 10;
+
+    scenario.run("Shoot None*20");
+    #[rustfmt::skip]
+    let expected = concat!(
+        "\n",
+        "   c\n",
+        "   c\n",
+    );
+    scenario.assert_field(expected);
+}
+
+#[test]
+fn simultaneous_erase_effects_pause_scroll() {
+    #[rustfmt::skip]
+    let field = concat!(
+        "aa  bb\n",
+        "a    b\n",
+    );
+    let mut scenario = Scenario::new(field, "  cc\n\n");
+    scenario.set_players(&[1, 4]);
+    scenario.game.scroll_wait = 17;
+
+    // 2つの矩形が同時に消えている間はスクロールせず、待ちが尽きても0のまま保留する
+    scenario.run("Shoot,Shoot None*16");
+    assert_eq!(scenario.game.erasing_effects.len(), 2);
+    assert_eq!(scenario.game.scroll_wait, 0);
+    #[rustfmt::skip]
+    let erasing = concat!(
+        "**  **\n",
+        " *   *\n",
+    );
+    scenario.assert_field(erasing);
+
+    scenario.run("None*20");
+    scenario.assert_field("  cc\n");
+    assert_eq!(scenario.game.players[0].score, 40);
+    assert_eq!(scenario.game.players[1].score, 40);
+}
+
+#[test]
+fn wipe_erases_rectangle_row_by_row() {
+    #[rustfmt::skip]
+    let field = concat!(
+        "          aa\n",
+        "           a\n",
+    );
+    let mut scenario = Scenario::new(field, "\n\n\n\n");
+    scenario.set_players(&[10]);
+    scenario.game.erase_style = EraseStyle::Wipe;
+
+    scenario.run("Shoot None*15");
+    #[rustfmt::skip]
+    let erasing = concat!(
+        "          **\n",
+        "          **\n",
+    );
+    scenario.assert_field(erasing);
+
+    scenario.run("None*2");
+    scenario.assert_field("          **\n");
+    scenario.run("None*2");
+    scenario.assert_field("");
+    assert_eq!(scenario.game.score, 40);
+}